@@ -0,0 +1,22 @@
+use rust2718::read::{repl, Prompt};
+
+fn start_logging() {
+    use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(EnvFilter::from_default_env())
+        .init();
+}
+
+fn main() {
+    start_logging();
+
+    match Prompt::new() {
+        Ok(prompt) => repl(&mut prompt.single_line(), Ok),
+        Err(e) => {
+            eprintln!("{}", &e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use rust2718::read::run;
+use rust2718::read::run_args;
 
 fn start_logging() {
     use tracing_subscriber::{fmt, prelude::*, EnvFilter};
@@ -11,5 +11,5 @@ fn start_logging() {
 
 fn main() {
     start_logging();
-    run();
+    std::process::exit(run_args(std::env::args().skip(1)));
 }
@@ -0,0 +1,15 @@
+use rust2718::read::run_args;
+
+fn start_logging() {
+    use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(EnvFilter::from_default_env())
+        .init();
+}
+
+fn main() {
+    start_logging();
+    std::process::exit(run_args(std::env::args().skip(1)));
+}
@@ -1,18 +1,113 @@
 /*!
 Then ENVIRONMENT.
+
+Top-level definitions live in namespaces: `Env`s whose outer environment
+is `core`, where the builtins are. `Env::default()` starts in `user`, and
+the `ns` special form switches to (or creates) another one. A symbol
+like `str/join` names `join` in the namespace `str`, or in whichever
+namespace the current one aliases as `str`. `require` loads namespaces
+that don't exist yet from files; see `module`. Environments are values
+too: `*ENV*` is the one it's evaluated in, and `env-new` makes more.
+
+```text
+user> (ns str)
+str> (def! join (fn* [sep xs] ...))
+str> (ns user (:require [str :as s]))
+user> (s/join ", " ["a" "b"])
+```
 */
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Debug, Formatter},
     ops::Deref,
-    sync::{Arc, RwLock},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock, Weak},
+};
+
+use crate::{
+    error::{err, rerr},
+    eval::eval,
+    read::read_str,
+    types::Val,
+    MalErr, Res,
 };
 
-use crate::{error::err, types::Val, Res};
+mod module;
+
+/// Definitions written in mal itself, evaluated in every new default `Env`.
+const PRELUDE: &[&str] = &[
+    "(def! not (fn* (a) (if a false true)))",
+    "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
+];
+
+/// The namespace the builtins and the prelude are defined in.
+pub const CORE: &str = "core";
+
+/// The namespace a default `Env` starts in.
+pub const USER: &str = "user";
 
 #[derive(Debug)]
 pub struct Env {
     outer: Option<Arc<Env>>,
     map: RwLock<BTreeMap<Box<str>, Val>>,
+    /// Set if this is a namespace's top-level environment.
+    ns: Option<Namespace>,
+}
+
+struct Namespace {
+    name: Arc<str>,
+    /// Other namespaces, by the names this one knows them as.
+    aliases: RwLock<BTreeMap<Arc<str>, Arc<Env>>>,
+    /// Names referred from other namespaces, and where they're from.
+    referred: RwLock<BTreeMap<Box<str>, Arc<Env>>>,
+    /// Weak, since the registry holds this namespace.
+    registry: Weak<Registry>,
+    /// Set in `core`, which every environment leads to, so the registry
+    /// lives as long as any of them.
+    _owner: Option<Arc<Registry>>,
+}
+
+/// Every namespace of one `Env::default()`, and which one is current.
+struct Registry {
+    namespaces: RwLock<BTreeMap<Arc<str>, Arc<Env>>>,
+    current: RwLock<Arc<str>>,
+    /// The namespace each file loaded by `require` left current, by
+    /// canonical path.
+    modules: RwLock<BTreeMap<PathBuf, Arc<Env>>>,
+    /// The files being loaded, outermost first, with the namespace each
+    /// was required as.
+    loading: Mutex<Vec<(PathBuf, Arc<str>)>>,
+    /// Where `require` looks for files: `MAL_PATH`, unless replaced.
+    search_path: RwLock<Vec<PathBuf>>,
+}
+
+impl Debug for Namespace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // The registry leads back to this namespace, so leave it out.
+        f.debug_struct("Namespace")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Namespace {
+    fn new(name: &str, registry: &Arc<Registry>) -> Namespace {
+        Namespace {
+            name: name.into(),
+            aliases: RwLock::default(),
+            referred: RwLock::default(),
+            registry: Arc::downgrade(registry),
+            _owner: None,
+        }
+    }
+}
+
+/// Splits a namespace-qualified name like `str/join` into its parts.
+fn qualified(s: &str) -> Option<(&str, &str)> {
+    match s.split_once('/') {
+        Some((ns, name)) if !ns.is_empty() && !name.is_empty() => Some((ns, name)),
+        _ => None,
+    }
 }
 
 impl Env {
@@ -20,6 +115,7 @@ impl Env {
         Env {
             outer: Some(outer.clone()),
             map: RwLock::new(BTreeMap::default()),
+            ns: None,
         }
         .into()
     }
@@ -33,11 +129,30 @@ impl Env {
     }
 
     fn self_get(self: &Arc<Env>, key: &str) -> Option<Val> {
-        self.map.read().unwrap().get(key).cloned()
+        if let Some(val) = self.map.read().unwrap().get(key) {
+            return Some(val.clone());
+        }
+        let from = self
+            .ns
+            .as_ref()?
+            .referred
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()?;
+        let val = from.map.read().unwrap().get(key).cloned();
+        val
     }
 
     pub fn get<S: AsRef<str>>(self: &Arc<Env>, s: S) -> Res {
         let s = s.as_ref();
+        if let Some((ns, name)) = qualified(s) {
+            let envt = self
+                .find_ns(ns)
+                .ok_or_else(|| err(format!("no namespace '{}'", ns)))?;
+            let val = envt.map.read().unwrap().get(name).cloned();
+            return val.ok_or_else(|| err(format!("'{}' not found", s)));
+        }
         let envt = self
             .find(s)
             .ok_or_else(|| err(format!("'{}' not found", s)))?;
@@ -56,24 +171,348 @@ impl Env {
         }
     }
 
+    /// The outermost environment: the `core` namespace.
+    pub fn root(self: &Arc<Env>) -> Arc<Env> {
+        match &self.outer {
+            Some(a) => a.root(),
+            None => self.clone(),
+        }
+    }
+
+    /// The namespace this environment is in.
+    pub fn namespace(self: &Arc<Env>) -> Arc<Env> {
+        match (&self.ns, &self.outer) {
+            (None, Some(a)) => a.namespace(),
+            _ => self.clone(),
+        }
+    }
+
+    fn ns(&self) -> &Namespace {
+        match (&self.ns, &self.outer) {
+            (Some(ns), _) => ns,
+            (None, Some(a)) => a.ns(),
+            (None, None) => panic!("environment outside any namespace"),
+        }
+    }
+
+    fn registry(&self) -> Arc<Registry> {
+        let registry = self.ns().registry.upgrade();
+        registry.expect("namespace outlived core")
+    }
+
+    /// The name of the namespace this environment is in.
+    pub fn ns_name(&self) -> Arc<str> {
+        self.ns().name.clone()
+    }
+
+    /// The current namespace: the one the REPL and `eval` evaluate in.
+    pub fn current_ns(&self) -> Arc<Env> {
+        let registry = self.registry();
+        let name = registry.current.read().unwrap().clone();
+        let envt = registry.namespaces.read().unwrap()[&name].clone();
+        envt
+    }
+
+    /// Makes the namespace `name` current, creating it if need be, and
+    /// returns it.
+    pub fn in_ns(self: &Arc<Env>, name: &str) -> Arc<Env> {
+        let registry = self.registry();
+        let envt = registry
+            .namespaces
+            .write()
+            .unwrap()
+            .entry(name.into())
+            .or_insert_with(|| {
+                Env {
+                    outer: Some(self.root()),
+                    map: RwLock::default(),
+                    ns: Some(Namespace::new(name, &registry)),
+                }
+                .into()
+            })
+            .clone();
+        *registry.current.write().unwrap() = name.into();
+        self.root().set("*ns*", Val::Symbol(name.into()));
+        envt
+    }
+
+    /// Calls `f` with the current namespace, then makes that namespace
+    /// current again, so an `ns` form in a loaded file doesn't outlast
+    /// the load.
+    pub fn in_current_ns<T, F>(self: &Arc<Env>, f: F) -> T
+    where
+        F: FnOnce(&Arc<Env>) -> T,
+    {
+        let envt = self.current_ns();
+        let res = f(&envt);
+        envt.in_ns(&envt.ns_name());
+        res
+    }
+
+    /// The namespace called (or aliased as) `name` from this one.
+    pub fn find_ns(&self, name: &str) -> Option<Arc<Env>> {
+        let ns = self.ns();
+        if let Some(envt) = ns.aliases.read().unwrap().get(name) {
+            return Some(envt.clone());
+        }
+        let envt = self
+            .registry()
+            .namespaces
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned();
+        envt
+    }
+
+    /// The names of every namespace, in order.
+    pub fn all_ns(&self) -> Vec<Arc<str>> {
+        let registry = self.registry();
+        let names = registry
+            .namespaces
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        names
+    }
+
+    /// Lets this environment's namespace call the namespace `target` by
+    /// `alias`.
+    pub fn add_alias(&self, alias: &str, target: &Arc<Env>) {
+        let mut aliases = self.ns().aliases.write().unwrap();
+        aliases.insert(alias.into(), target.namespace());
+    }
+
+    /// Makes `names` (or all the public names, if `None`) of the
+    /// namespace `from` visible unqualified in this environment's
+    /// namespace.
+    pub fn refer(&self, from: &Arc<Env>, names: Option<Vec<Arc<str>>>) -> Result<(), MalErr> {
+        let from = from.namespace();
+        let names = match names {
+            Some(names) => names,
+            None => from.publics().into_iter().map(|(k, _)| k).collect(),
+        };
+        let mut referred = self.ns().referred.write().unwrap();
+        for name in names {
+            if !from.map.read().unwrap().contains_key(name.as_ref()) {
+                return rerr(format!("'{}' not found in {}", name, from.ns_name()));
+            }
+            referred.insert(name.as_ref().into(), from.clone());
+        }
+        Ok(())
+    }
+
+    /// The names defined in this environment's namespace (not those it
+    /// refers to), with their values, in order.
+    pub fn publics(self: &Arc<Env>) -> Vec<(Arc<str>, Val)> {
+        let envt = self.namespace();
+        let map = envt.map.read().unwrap();
+        map.iter()
+            .map(|(k, v)| (Arc::from(k.as_ref()), v.clone()))
+            .collect()
+    }
+
+    /// Every name visible from this environment, including those bound
+    /// in outer ones, in order.
+    pub fn keys(self: &Arc<Env>) -> BTreeSet<Arc<str>> {
+        let mut keys = match &self.outer {
+            Some(a) => a.keys(),
+            None => BTreeSet::new(),
+        };
+        keys.extend(
+            self.map
+                .read()
+                .unwrap()
+                .keys()
+                .map(|k| Arc::from(k.as_ref())),
+        );
+        if let Some(ns) = &self.ns {
+            keys.extend(
+                ns.referred
+                    .read()
+                    .unwrap()
+                    .keys()
+                    .map(|k| Arc::from(k.as_ref())),
+            );
+        }
+        keys
+    }
+
+    /**
+    Empties every namespace of this `Env`, which is unusable afterwards.
+    A function defined at the top level and the namespace it's defined
+    in refer to each other, so namespaces are never freed otherwise;
+    `:reset` closes the `Env` it replaces.
+    */
+    pub fn close(&self) {
+        let registry = self.registry();
+        let namespaces = std::mem::take(&mut *registry.namespaces.write().unwrap());
+        registry.modules.write().unwrap().clear();
+        for envt in namespaces.values() {
+            let map = std::mem::take(&mut *envt.map.write().unwrap());
+            drop(map);
+            if let Some(ns) = &envt.ns {
+                ns.aliases.write().unwrap().clear();
+                ns.referred.write().unwrap().clear();
+            }
+        }
+    }
+
     pub fn set(self: &Arc<Env>, key: &str, v: Val) {
         self.deref().map.write().unwrap().insert(key.into(), v);
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Arc<Env> {
-        use crate::types::{builtin, builtin::math, Builtin};
+        use crate::types::{
+            builtin,
+            builtin::{math, ns, numeric, seq},
+            Builtin, List,
+        };
 
         let mut map = BTreeMap::default();
-        for (name, func) in builtin::BUILTINS.iter().chain(math::BUILTINS.iter()) {
-            let f = Builtin::new(name, func);
-            let name: Box<str> = (*name).into();
-            map.insert(name, f.into());
+        for def in builtin::BUILTINS
+            .iter()
+            .chain(math::BUILTINS.iter())
+            .chain(numeric::BUILTINS.iter())
+            .chain(seq::BUILTINS.iter())
+        {
+            let name: Box<str> = def.0.into();
+            map.insert(name, Builtin::new(def).into());
+        }
+        for def in builtin::ENV_BUILTINS
+            .iter()
+            .chain(seq::ENV_BUILTINS.iter())
+            .chain(ns::ENV_BUILTINS.iter())
+        {
+            let name: Box<str> = def.0.into();
+            map.insert(name, Builtin::with_env(def).into());
         }
 
-        Env {
+        map.insert("*ARGV*".into(), List::empty().into());
+        map.insert("*host-language*".into(), Val::String("rust2718".into()));
+
+        let registry = Arc::new(Registry {
+            namespaces: RwLock::default(),
+            current: RwLock::new(CORE.into()),
+            modules: RwLock::default(),
+            loading: Mutex::default(),
+            search_path: RwLock::new(module::search_path()),
+        });
+        let core: Arc<Env> = Env {
             outer: None,
             map: RwLock::new(map),
+            ns: Some(Namespace {
+                _owner: Some(registry.clone()),
+                ..Namespace::new(CORE, &registry)
+            }),
         }
-        .into()
+        .into();
+        registry
+            .namespaces
+            .write()
+            .unwrap()
+            .insert(CORE.into(), core.clone());
+
+        for src in PRELUDE.iter() {
+            read_str(src)
+                .and_then(|form| eval(&core, form))
+                .expect("unable to evaluate prelude");
+        }
+
+        core.in_ns(USER)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
+
+    #[test]
+    fn first_class_environments() {
+        let envt = Env::default();
+        assert_eq!(show(&envt, "*ENV*"), "<environment in user>");
+        assert_eq!(show(&envt, "(let* [x 1] (eval 'x *ENV*))"), "1");
+
+        rep(&envt, "(def! e (env-new))").unwrap();
+        assert_eq!(show(&envt, "(env-set! e 'x 2)"), "2");
+        assert_eq!(show(&envt, "(eval '(+ x 1) e)"), "3");
+        assert_eq!(
+            show(&envt, "(env-get e 'count)"),
+            "<count (builtin function)>"
+        );
+        assert_eq!(show(&envt, "(= (env-get e '*outer*) *ENV*)"), "true");
+        assert!(rep(&envt, "x").is_err());
+
+        rep(&envt, "(def! inner (env-new e))").unwrap();
+        rep(&envt, "(eval '(def! y (* x 10)) inner)").unwrap();
+        assert_eq!(show(&envt, "(env-get inner 'y)"), "20");
+        assert!(rep(&envt, "(env-get e 'y)").is_err());
+        assert!(rep(&envt, "(eval 1 2)").is_err());
+    }
+
+    #[test]
+    fn close_frees_namespaces() {
+        let envt = Env::default();
+        rep(&envt, "(ns other)").unwrap();
+        rep(&envt.current_ns(), "(def! f (fn* [] f))").unwrap();
+        let core = Arc::downgrade(&envt.root());
+        let other = Arc::downgrade(&envt.find_ns("other").unwrap());
+        envt.close();
+        drop(envt);
+        assert!(core.upgrade().is_none());
+        assert!(other.upgrade().is_none());
+    }
+
+    #[test]
+    fn namespaces() {
+        let envt = Env::default();
+        assert_eq!(show(&envt.current_ns(), "*ns*"), "user");
+        rep(&envt, "(ns str)").unwrap();
+        assert_eq!(show(&envt.current_ns(), "*ns*"), "str");
+        rep(
+            &envt.current_ns(),
+            "(do (def! join (fn* [xs] (apply str xs))) (def! x 1))",
+        )
+        .unwrap();
+        assert_eq!(
+            show(&envt.current_ns(), "(ns user (:require [str :as s]))"),
+            "nil"
+        );
+        assert_eq!(envt.current_ns().ns_name().as_ref(), "user");
+
+        assert_eq!(show(&envt.current_ns(), "(s/join [1 2])"), "\"12\"");
+        assert_eq!(show(&envt.current_ns(), "(str/join [3])"), "\"3\"");
+        assert_eq!(show(&envt.current_ns(), "(core/count [1 2])"), "2");
+        assert_eq!(show(&envt.current_ns(), "(/ 6 3)"), "2");
+        assert!(rep(&envt, "join").is_err());
+        assert!(rep(&envt, "nope/join").is_err());
+
+        rep(&envt, "(require '[str :refer [join]])").unwrap();
+        assert_eq!(show(&envt.current_ns(), "(join [4])"), "\"4\"");
+        assert!(rep(&envt, "x").is_err());
+        rep(&envt, "(refer 'str)").unwrap();
+        assert_eq!(show(&envt.current_ns(), "x"), "1");
+        rep(&envt, "(alias 't 'str)").unwrap();
+        assert_eq!(show(&envt.current_ns(), "t/x"), "1");
+
+        // Names defined in user don't leak into str.
+        rep(&envt, "(def! y 2)").unwrap();
+        assert!(rep(&envt.find_ns("str").unwrap(), "y").is_err());
+        assert_eq!(show(&envt.current_ns(), "(count (ns-publics 'str))"), "2");
+        assert_eq!(show(&envt.current_ns(), "(get (ns-publics 'str) 'x)"), "1");
+        assert_eq!(show(&envt.current_ns(), "(all-ns)"), "(core str user)");
+        assert!(rep(&envt, "(require 'nope)").is_err());
+
+        // An ns form inside another one doesn't leave its scope.
+        assert_eq!(show(&envt, "(let* [x 3] (do (ns other) x))"), "3");
+        assert_eq!(envt.current_ns().ns_name().as_ref(), "other");
     }
 }
@@ -0,0 +1,199 @@
+/*!
+Loading namespaces from files.
+
+`(require 'app.util)`, when there's no namespace `app.util` yet, looks
+for `app/util.mal` in each directory of `MAL_PATH` (separated like
+`PATH`; just the current directory if it's unset) and evaluates the
+first one found in a new namespace `app.util`. Each file is loaded once
+per `Env::default()`, however it's reached. `MAL_PATH` is read when the
+`Env` is made; `Env::set_search_path` replaces it.
+*/
+use std::{ffi::OsString, path::PathBuf, sync::Arc};
+
+use super::{Env, Registry};
+use crate::{error::rerr, eval::eval_forms, read::read_file, MalErr};
+
+/// The directories `MAL_PATH` lists.
+pub fn search_path() -> Vec<PathBuf> {
+    let path = std::env::var_os("MAL_PATH").unwrap_or_else(|| OsString::from("."));
+    std::env::split_paths(&path).collect()
+}
+
+/// The canonical path of the file defining the namespace `name`, in one
+/// of the directories `dirs`.
+fn locate(name: &str, dirs: &[PathBuf]) -> Result<PathBuf, MalErr> {
+    let file = name.split('.').collect::<PathBuf>().with_extension("mal");
+    for dir in dirs {
+        let path = dir.join(&file);
+        if path.is_file() {
+            return match path.canonicalize() {
+                Ok(path) => Ok(path),
+                Err(e) => rerr(format!("unable to find {:?}: {}", &path, &e)),
+            };
+        }
+    }
+    rerr(format!(
+        "no namespace '{}', and no {} in MAL_PATH",
+        name,
+        file.display()
+    ))
+}
+
+impl Registry {
+    /// Fails if requiring `name` would load a file that's still loading,
+    /// as found by `is_loading`.
+    fn check_cycle<F>(&self, is_loading: F, name: &str) -> Result<(), MalErr>
+    where
+        F: Fn(&(PathBuf, Arc<str>)) -> bool,
+    {
+        let loading = self.loading.lock().unwrap();
+        match loading.iter().position(is_loading) {
+            Some(i) => {
+                let cycle: Vec<&str> = loading[i..]
+                    .iter()
+                    .map(|(_, n)| n.as_ref())
+                    .chain([name])
+                    .collect();
+                rerr(format!("circular require: {}", cycle.join(" -> ")))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Env {
+    /// Makes `require` look for files in `dirs`, in order, for every
+    /// namespace of this `Env`.
+    pub fn set_search_path(&self, dirs: Vec<PathBuf>) {
+        *self.registry().search_path.write().unwrap() = dirs;
+    }
+
+    /**
+    The namespace called (or aliased as) `name` from this one, loading
+    it from its file if it doesn't exist yet. Requiring a file that's
+    still being loaded is an error.
+    */
+    pub fn require_ns(self: &Arc<Env>, name: &str) -> Result<Arc<Env>, MalErr> {
+        let registry = self.registry();
+        // A namespace being loaded already exists, but isn't ready.
+        registry.check_cycle(|(_, n)| n.as_ref() == name, name)?;
+        // A file that switched to a namespace of its own is found by
+        // its path, not by `name`.
+        let path = locate(name, &registry.search_path.read().unwrap());
+        if let Ok(path) = &path {
+            if let Some(envt) = registry.modules.read().unwrap().get(path) {
+                return Ok(envt.clone());
+            }
+        }
+        if let Some(envt) = self.find_ns(name) {
+            return Ok(envt);
+        }
+        let path = path?;
+        registry.check_cycle(|(p, _)| p == &path, name)?;
+
+        registry
+            .loading
+            .lock()
+            .unwrap()
+            .push((path.clone(), name.into()));
+        let res: Result<Arc<Env>, MalErr> = self.in_current_ns(|_| {
+            let envt = self.in_ns(name);
+            eval_forms(&envt, read_file(&path.to_string_lossy())?)?;
+            // The file may have switched to a namespace of its own, in
+            // which case `name` was only a placeholder.
+            let current = envt.current_ns();
+            if !Arc::ptr_eq(&current, &envt) {
+                registry.namespaces.write().unwrap().remove(name);
+            }
+            Ok(current)
+        });
+        registry.loading.lock().unwrap().pop();
+
+        match res {
+            Ok(envt) => {
+                registry.modules.write().unwrap().insert(path, envt.clone());
+                Ok(envt)
+            }
+            Err(e) => {
+                // Let a later require try again from scratch.
+                registry.namespaces.write().unwrap().remove(name);
+                Err(e.wrap(format!("while requiring {}", name)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::eval::test::{rep, show};
+
+    /// A directory of files for a test, removed however the test ends.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn write(&self, file: &str, text: &str) {
+            let path = self.0.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn requires_files_once() {
+        let dir = TempDir::new("mal-modules");
+        dir.write("counter.mal", "(def! n (atom 0))");
+        dir.write(
+            "app/util.mal",
+            "(require 'counter) (swap! counter/n + 1) (def! twice (fn* [x] (* 2 x)))",
+        );
+        dir.write("lib.mal", "(ns mylib) (def! twice (fn* [x] (* 2 x)))");
+        dir.write("a.mal", "(ns a (:require b))");
+        dir.write("b.mal", "(require 'a)");
+
+        let envt = Env::default();
+        // `util` and `app.util` are the same file.
+        envt.set_search_path(vec![dir.path().into(), dir.path().join("app")]);
+        let user = envt.current_ns();
+
+        assert_eq!(show(&user, "(require '[app.util :as u])"), "nil");
+        assert_eq!(show(&user, "(u/twice 4)"), "8");
+        assert_eq!(show(&user, "(require 'util 'app.util)"), "nil");
+        assert_eq!(show(&user, "@counter/n"), "1");
+        assert_eq!(show(&user, "*ns*"), "user");
+        assert!(rep(&user, "twice").is_err());
+
+        // A file with an ns form of its own is still found by its name.
+        assert_eq!(show(&user, "(require '[lib :as l])"), "nil");
+        assert_eq!(show(&user, "(require '[lib :as m])"), "nil");
+        assert_eq!(show(&user, "(m/twice 5)"), "10");
+        assert_eq!(show(&user, "(l/twice 6)"), "12");
+        assert_eq!(
+            show(&user, "(all-ns)"),
+            "(app.util core counter mylib user)"
+        );
+
+        let e = rep(&user, "(require 'a)").unwrap_err();
+        assert!(format!("{}", e).contains("circular require: a -> b -> a"));
+        assert!(rep(&user, "(require 'nope)").is_err());
+        assert_eq!(show(&user, "*ns*"), "user");
+    }
+}
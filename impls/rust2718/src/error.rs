@@ -6,12 +6,25 @@ use std::{
     fmt::{Display, Formatter},
 };
 
-use crate::types::Val;
+use crate::{
+    span::{self, Span},
+    types::Val,
+};
+
+/// One step of an error's trace: what was going on, and where in the
+/// source, if that's known.
+#[derive(Debug)]
+pub struct Frame {
+    pub msg: Cow<'static, str>,
+    pub span: Option<Span>,
+}
 
 #[derive(Debug)]
 pub struct MalErr {
     pub msg: Cow<'static, str>,
-    pub context: Vec<Cow<'static, str>>,
+    pub context: Vec<Frame>,
+    /// The value passed to `throw`, if this error was thrown from mal code.
+    pub val: Option<Val>,
 }
 
 impl MalErr {
@@ -20,7 +33,23 @@ impl MalErr {
         Cow<'static, str>: From<C>,
     {
         let mut e = self;
-        e.context.push(msg.into());
+        e.context.push(Frame {
+            msg: msg.into(),
+            span: None,
+        });
+        e
+    }
+
+    /// Like `wrap`, but noting where in the source `msg` happened.
+    pub fn wrap_at<C>(self, msg: C, span: Span) -> MalErr
+    where
+        Cow<'static, str>: From<C>,
+    {
+        let mut e = self;
+        e.context.push(Frame {
+            msg: msg.into(),
+            span: Some(span),
+        });
         e
     }
 
@@ -28,11 +57,23 @@ impl MalErr {
         match res {
             Ok(v) => Ok(v),
             Err(mut e) => {
-                e.context.push(format!("in form {}", &val).into());
+                e.context.push(Frame {
+                    msg: format!("in form {}", &val).into(),
+                    span: span::of(&val),
+                });
                 Err(e)
             }
         }
     }
+
+    /// The value a `catch*` clause binds: the thrown value, or the
+    /// message of an internal error.
+    pub fn value(&self) -> Val {
+        match &self.val {
+            Some(v) => v.clone(),
+            None => Val::String(self.msg.as_ref().into()),
+        }
+    }
 }
 
 pub fn err<C>(msg: C) -> MalErr
@@ -42,6 +83,15 @@ where
     MalErr {
         msg: msg.into(),
         context: Vec::new(),
+        val: None,
+    }
+}
+
+pub fn thrown(val: Val) -> MalErr {
+    MalErr {
+        msg: format!("{}", &val).into(),
+        context: Vec::new(),
+        val: Some(val),
     }
 }
 
@@ -54,8 +104,13 @@ where
 
 impl Display for MalErr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for line in self.context.iter().rev() {
-            writeln!(f, "! {}", line)?;
+        for frame in self.context.iter().rev() {
+            writeln!(f, "! {}", &frame.msg)?;
+            if let Some(span) = &frame.span {
+                writeln!(f, "!   at {}", span)?;
+                writeln!(f, "!     {}", &span.text)?;
+                writeln!(f, "!     {}", span.caret())?;
+            }
         }
         writeln!(f, "ERROR: {}", &self.msg)
     }
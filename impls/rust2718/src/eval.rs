@@ -2,33 +2,179 @@
 Environments and evaluation.
 */
 
-use std::{ops::Deref, sync::Arc};
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use tracing::{event, Level};
 
 use crate::{
     env::Env,
     error::rerr,
-    types::{Function, List, Map},
+    types::{builtin::ns, Clause, Function, Lambda, List, Map, Params, Pattern, SeqParts, Vector},
     MalErr, Res, Val,
 };
 
+/// The symbols `eval` treats specially at the head of a list.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "def!",
+    "defmacro!",
+    "macroexpand",
+    "quote",
+    "quasiquoteexpand",
+    "quasiquote",
+    "try*",
+    "catch*",
+    "let",
+    "let*",
+    "do",
+    "if",
+    "ns",
+    "fn",
+    "fn*",
+];
+
+/// How many forms have been evaluated, counting each tail call.
+static EVALS: AtomicU64 = AtomicU64::new(0);
+
+/// How many forms have been evaluated so far, in every thread; compare
+/// two readings to see how much work something took.
+pub fn eval_count() -> u64 {
+    EVALS.load(Ordering::Relaxed)
+}
+
 pub fn eval(envt: &Arc<Env>, ast: Val) -> Res {
     event!(Level::TRACE, "eval( {:?}, {:?} )", &envt, &ast);
 
-    let res = match ast.clone() {
-        Val::List(a) => apply(envt, a),
-        x => eval_ast(envt, x),
-    };
+    let res = eval_tco(envt.clone(), ast.clone());
     MalErr::in_form(res, ast)
 }
 
+/// Evaluates `forms` in order, as the top-level forms of a file: each in
+/// whichever namespace is current when it's reached, so the forms after
+/// an `ns` form are in the namespace it switches to. Returns the value
+/// of the last one.
+pub fn eval_forms(envt: &Arc<Env>, forms: Vec<Val>) -> Res {
+    let mut val = Val::Nil;
+    for form in forms {
+        val = eval(&envt.current_ns(), form)?;
+    }
+    Ok(val)
+}
+
+/**
+The evaluation loop proper.
+
+Forms in tail position (the chosen branch of an `if`, the last form of a
+`do` or `let*`, and the body of a called `Function`) replace `ast` (and
+possibly `envt`) and go back around the loop instead of recursing, so
+tail calls run in constant stack. `eval` notes the form it was given in
+an error's trace; the loop notes the tail form that failed, if any.
+*/
+fn eval_tco(mut envt: Arc<Env>, mut ast: Val) -> Res {
+    let mut tail = false;
+    loop {
+        EVALS.fetch_add(1, Ordering::Relaxed);
+        let form = ast.clone();
+        match eval_step(&mut envt, &mut ast) {
+            Ok(Some(val)) => return Ok(val),
+            Ok(None) => tail = true,
+            Err(e) if tail => return MalErr::in_form(Err(e), form),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Evaluates `ast`, unless it's in tail position: then it's replaced
+/// (along with `envt`, maybe) by the form to evaluate next, and the
+/// result is `None`.
+fn eval_step(envt: &mut Arc<Env>, ast: &mut Val) -> Result<Option<Val>, MalErr> {
+    let list = match macroexpand(envt, ast.clone())? {
+        Val::List(a, _) => a,
+        x => return eval_ast(envt, x).map(Some),
+    };
+
+    let car = match list.car() {
+        Ok(val) => val,
+        Err(_) => return Ok(Some(list.into())),
+    };
+    let rest = list.cdr()?;
+
+    if let Val::Symbol(s) = &car {
+        let val = match s.deref() {
+            "def!" => {
+                let (key, doc, form) = def_parts(rest)?;
+                let val = eval(envt, form)?;
+                define(envt, key, document(val, doc)?)?
+            }
+            "defmacro!" => {
+                let (key, doc, form) = def_parts(rest)?;
+                let func = eval(envt, form)?.unwrap_func()?;
+                let mac = match func.as_function() {
+                    Some(f) => f.to_macro(),
+                    None => return rerr("defmacro! requires an interpreted function"),
+                };
+                define(envt, key, document(mac.into(), doc)?)?
+            }
+            "macroexpand" => macroexpand(envt, rest.car()?)?,
+            "quote" => rest.car()?,
+            "quasiquoteexpand" => quasiquote(rest.car()?)?,
+            "quasiquote" => {
+                *ast = quasiquote(rest.car()?)?;
+                return Ok(None);
+            }
+            "try*" => do_try(envt, rest)?,
+            "let" | "let*" => {
+                let new_envt = Env::child_of(envt);
+                *ast = do_let(&new_envt, rest)?;
+                *envt = new_envt;
+                return Ok(None);
+            }
+            "do" => {
+                *ast = do_do(envt, rest)?;
+                return Ok(None);
+            }
+            "if" => {
+                *ast = do_if(envt, rest)?;
+                return Ok(None);
+            }
+            "fn" | "fn*" => make_closure(envt, rest)?,
+            "ns" => do_ns(envt, rest)?,
+            _ => return eval_call(envt, ast, list),
+        };
+        return Ok(Some(val));
+    }
+    eval_call(envt, ast, list)
+}
+
+/// Calls the function at the head of `list`; an interpreted one's body
+/// replaces `ast`, as a tail form.
+fn eval_call(envt: &mut Arc<Env>, ast: &mut Val, list: Arc<List>) -> Result<Option<Val>, MalErr> {
+    let list = eval_ast(envt, list.into())?.unwrap_list()?;
+    let func = list.car()?.unwrap_func()?;
+    let args = list.cdr()?;
+    match func.as_function() {
+        Some(f) => {
+            let (fn_envt, form) = f.bind(args)?;
+            *envt = fn_envt;
+            *ast = form;
+            Ok(None)
+        }
+        None => func.call(envt, args).map(Some),
+    }
+}
+
 pub fn eval_ast(envt: &Arc<Env>, ast: Val) -> Res {
     event!(Level::TRACE, "eval_ast( {:?}, {:?} )", &envt, &ast);
 
     match ast {
-        Val::Symbol(s) => envt.get(s.as_ref()).map(|v| v.clone()),
-        Val::List(a) => {
+        Val::Symbol(s) if s.as_ref() == "*ENV*" => Ok(Val::Env(envt.clone())),
+        Val::Symbol(s) => envt.get(s.as_ref()),
+        Val::List(a, _) => {
             let mut a = a.clone();
             let mut v: Vec<Val> = Vec::new();
 
@@ -43,134 +189,434 @@ pub fn eval_ast(envt: &Arc<Env>, ast: Val) -> Res {
 
             Ok(a.into())
         }
-        Val::Vector(a) => {
-            let v: Vec<Val> = a
-                .read()
-                .unwrap()
+        Val::Vector(a, _) => {
+            let v: Vector = a
                 .iter()
                 .cloned()
                 .map(|v| eval(envt, v))
-                .collect::<Result<Vec<_>, MalErr>>()?;
+                .collect::<Result<_, MalErr>>()?;
             Ok(v.into())
         }
-        Val::Map(a) => {
-            let new_map = Arc::new(Map::default());
+        Val::Map(a, _) => {
+            let mut new_map = Map::default();
             for (k, v) in a.iter() {
-                new_map.insert(k, eval(envt, v)?)?;
+                new_map = new_map.assoc(eval(envt, k)?, eval(envt, v)?);
             }
-            let v: Val = new_map.into();
-            Ok(v)
+            Ok(new_map.into())
         }
         x => Ok(x),
     }
 }
 
-fn apply(envt: &Arc<Env>, list: Arc<List>) -> Res {
-    event!(Level::TRACE, "apply([ Env ], {:?})", &list);
+/// Whether `ast` is a list whose head names a macro; if so, returns the macro.
+fn macro_call(envt: &Arc<Env>, ast: &Val) -> Option<Arc<dyn Lambda>> {
+    let sym = match ast {
+        Val::List(list, _) => list.car().ok()?.unwrap_symbol().ok()?,
+        _ => return None,
+    };
+    let func = envt.get(sym.as_ref()).ok()?.unwrap_func().ok()?;
+    match func.as_function() {
+        Some(f) if f.is_macro() => Some(func),
+        _ => None,
+    }
+}
 
-    let car = match list.car() {
-        Ok(val) => val,
-        Err(_) => return Ok(list.into()),
+/// Expands `ast` until it is no longer a macro call.
+fn macroexpand(envt: &Arc<Env>, ast: Val) -> Res {
+    let mut ast = ast;
+    while let Some(mac) = macro_call(envt, &ast) {
+        let args = ast.unwrap_list()?.cdr()?;
+        ast = mac.call(envt, args)?;
+    }
+    Ok(ast)
+}
+
+/// Returns the form that a `quasiquote`d `ast` evaluates as.
+fn quasiquote(ast: Val) -> Res {
+    let quote = |v: Val| -> Val { List::from_val(v).cons(Val::Symbol("quote".into())).into() };
+
+    match ast {
+        Val::List(ref list, _) => {
+            if let Ok(Val::Symbol(s)) = list.car() {
+                if s.as_ref() == "unquote" {
+                    return list.cdr()?.car();
+                }
+            }
+            quasiquote_seq(ast.unwrap_seq()?)
+        }
+        Val::Vector(_, _) => {
+            let expanded = quasiquote_seq(ast.unwrap_seq()?)?;
+            Ok(List::from_val(expanded)
+                .cons(Val::Symbol("vec".into()))
+                .into())
+        }
+        Val::Symbol(_) | Val::Map(_, _) => Ok(quote(ast)),
+        x => Ok(x),
+    }
+}
+
+fn quasiquote_seq(elts: Vec<Val>) -> Res {
+    let mut acc: Val = List::empty().into();
+    for elt in elts.into_iter().rev() {
+        let spliced = match &elt {
+            Val::List(list, _) => match list.car() {
+                Ok(Val::Symbol(s)) if s.as_ref() == "splice-unquote" => Some(list.cdr()?.car()?),
+                _ => None,
+            },
+            _ => None,
+        };
+        let (func, val) = match spliced {
+            Some(v) => ("concat", v),
+            None => ("cons", quasiquote(elt)?),
+        };
+        acc = List::from_vec(vec![Val::Symbol(func.into()), val, acc]).into();
+    }
+    Ok(acc)
+}
+
+/// Evaluates the body of a `try*`, handing any error to the `catch*` clause.
+fn do_try(envt: &Arc<Env>, list: Arc<List>) -> Res {
+    let mut list = list.clone();
+    let body = list.pop()?;
+    let res = eval(envt, body);
+
+    let (e, mut clause) = match (res, list.next()) {
+        (Err(e), Some(Val::List(clause, _))) => (e, clause),
+        (res, _) => return res,
     };
-    let rest = list.cdr()?;
+    match clause.pop()? {
+        Val::Symbol(s) if s.as_ref() == "catch*" => {}
+        _ => return rerr("expected a catch* clause"),
+    }
+    let sym = clause.pop()?.unwrap_symbol()?;
+    let catch_envt = Env::binding(envt, vec![(sym, e.value())]);
+    eval(&catch_envt, clause.pop()?)
+}
 
-    match car {
-        Val::Symbol(s) => match s.deref() {
-            "def!" => return define(envt, rest.car()?, eval(envt, rest.cdr()?.car()?)?),
-            "let" | "let*" => return do_let(&Env::child_of(envt), rest),
-            "do" => return do_do(envt, rest),
-            "if" => return do_if(envt, rest),
-            "fn" | "fn*" => return make_closure(envt, rest),
-            _ => {}
-        },
-        _ => {}
+/// Splits the rest of `(def! name "docstring" form)` into its parts; the
+/// docstring is optional.
+fn def_parts(rest: Arc<List>) -> Result<(Val, Option<Arc<str>>, Val), MalErr> {
+    match Val::from(rest).unwrap_seq()?.as_slice() {
+        [key, form] => Ok((key.clone(), None, form.clone())),
+        [key, Val::String(doc), form] => Ok((key.clone(), Some(doc.clone()), form.clone())),
+        _ => rerr("def! takes a name, an optional docstring and a value"),
     }
+}
 
-    let list = eval_ast(envt, list.into())?.unwrap_list()?;
-    let func = list.car()?.unwrap_func()?;
-    let rest = list.cdr()?;
-    func.call(envt, rest)
+/// Gives `val` the docstring `doc`, if there is one.
+fn document(val: Val, doc: Option<Arc<str>>) -> Res {
+    match (val, doc) {
+        (val, None) => Ok(val),
+        (Val::Func(f, meta), Some(doc)) => Ok(Val::Func(f.with_doc(doc), meta)),
+        (_, Some(_)) => rerr("a docstring can only document a function"),
+    }
 }
 
 fn define(envt: &Arc<Env>, key: Val, val: Val) -> Res {
     let key = key.unwrap_symbol()?;
+    if let Val::Func(f, _) = &val {
+        if let Some(f) = f.as_function() {
+            if f.name().is_none() {
+                f.set_name(&key);
+            }
+        }
+    }
     envt.set(&key, val.clone());
     Ok(val)
 }
 
+/// Binds the `let*` bindings in `new_envt` and returns the body form.
 fn do_let(new_envt: &Arc<Env>, rest: Arc<List>) -> Res {
     let mut rest = rest.clone();
-    match rest.pop()? {
-        Val::List(mut a) => loop {
-            let key = match a.next() {
-                Some(s) => s.unwrap_symbol()?,
-                None => break,
-            };
-            let val = eval(new_envt, a.pop()?)?;
-            new_envt.set(&key, val);
-        },
-        Val::Vector(a) => {
-            for chunk in a.read().unwrap().chunks(2) {
-                let (key, val) = match chunk {
-                    [k, v] => (k.unwrap_symbol()?, eval(new_envt, v.clone())?),
-                    _ => return rerr("binding form must contain even number of elements"),
-                };
-                new_envt.set(&key, val);
-            }
-        }
+    let bindings = match rest.pop()? {
+        x @ (Val::List(_, _) | Val::Vector(_, _)) => x.unwrap_seq()?,
         _ => return rerr("binding form must be a list or a vector"),
+    };
+    for chunk in bindings.chunks(2) {
+        let (pat, form) = match chunk {
+            [p, f] => (Pattern::read(p)?, f.clone()),
+            _ => return rerr("binding form must contain even number of elements"),
+        };
+        pat.bind(new_envt, eval(new_envt, form)?)?;
     }
 
-    eval(new_envt, rest.next().unwrap_or(Val::Nil))
+    Ok(rest.next().unwrap_or(Val::Nil))
 }
 
+/// Evaluates all but the last form and returns the last one.
 fn do_do(envt: &Arc<Env>, list: Arc<List>) -> Res {
     let mut forms = list.clone();
     while let Some(val) = forms.next() {
         if forms.is_empty() {
-            return eval(envt, val);
-        } else {
-            let _ = eval(envt, val)?;
+            return Ok(val);
+        }
+        let _ = eval(envt, val)?;
+    }
+    Ok(Val::Nil)
+}
+
+/**
+Makes the namespace named by the rest of an `ns` form current, creating
+it if need be, then requires what its `:require` clauses name:
+
+```text
+(ns app
+  (:require [str :as s]
+            [seqs :refer [take drop]]))
+```
+*/
+fn do_ns(envt: &Arc<Env>, list: Arc<List>) -> Res {
+    let mut list = list.clone();
+    let ns = envt.in_ns(&list.pop()?.unwrap_symbol()?);
+    while let Some(clause) = list.next() {
+        let mut clause = clause.unwrap_list()?;
+        if !matches!(clause.pop()?, Val::Keyword(k) if k.as_ref() == "require") {
+            return rerr("ns only takes (:require spec...) clauses");
+        }
+        while let Some(spec) = clause.next() {
+            ns::require_spec(&ns, &spec)?;
         }
     }
     Ok(Val::Nil)
 }
 
+/// Evaluates the condition and returns the branch to take.
 fn do_if(envt: &Arc<Env>, list: Arc<List>) -> Res {
     let mut list = list.clone();
     let cond = eval(envt, list.pop()?)?;
     match cond {
         Val::Nil | Val::False => {
             let _ = list.pop()?;
-            if let Some(val) = list.next() {
-                eval(envt, val)
-            } else {
-                Ok(Val::Nil)
-            }
+            Ok(list.next().unwrap_or(Val::Nil))
         }
-        _ => eval(envt, list.pop()?),
+        _ => list.pop(),
     }
 }
 
+/**
+Creates a `Function` from the rest of an `fn*` form: either a single
+`params body` pair, or one or more `(params body)` clauses, as in
+
+```text
+(fn* ([x] (f x 1))
+     ([x y] (* x y)))
+```
+
+either of which may follow a docstring.
+*/
 fn make_closure(envt: &Arc<Env>, list: Arc<List>) -> Res {
-    let mut list = list.clone();
-    let mut args: Vec<Arc<str>> = Vec::new();
-    match list.pop()? {
-        Val::Nil => {}
-        Val::Symbol(s) => args.push(s.clone()),
-        Val::List(mut l) => {
-            while let Some(v) = l.next() {
-                args.push(v.unwrap_symbol()?.clone());
-            }
-        }
-        Val::Vector(v) => {
-            for val in v.read().unwrap().iter() {
-                args.push(val.unwrap_symbol()?);
-            }
-        }
-        _ => return rerr("expected an argument list"),
+    let mut forms = Val::from(list).unwrap_seq()?;
+    let doc = match forms.as_slice() {
+        [Val::String(doc), _, ..] => Some(doc.clone()),
+        _ => None,
+    };
+    if doc.is_some() {
+        forms.remove(0);
+    }
+    let multi = match forms.first() {
+        Some(Val::List(..)) => forms.iter().all(is_clause),
+        _ => false,
+    };
+    let clauses = if multi {
+        forms
+            .into_iter()
+            .map(|clause| {
+                let mut clause = clause.unwrap_list()?;
+                read_clause(&mut clause)
+            })
+            .collect::<Result<Vec<_>, MalErr>>()?
+    } else {
+        vec![read_clause(&mut List::from_vec(forms))?]
     };
 
+    let func = Function::define(clauses, envt)?;
+    match doc {
+        Some(doc) => Ok(func.documented(doc).into()),
+        None => Ok(func.into()),
+    }
+}
+
+/**
+Whether `v` looks like a `([params] body)` clause of a multi-arity `fn*`.
+The parameters must be a vector: a single-arity body such as
+`((get fs a) b)` also starts with a list.
+*/
+fn is_clause(v: &Val) -> bool {
+    match v {
+        Val::List(list, _) => matches!(list.car(), Ok(Val::Vector(_, _))),
+        _ => false,
+    }
+}
+
+fn read_clause(list: &mut Arc<List>) -> Result<Clause, MalErr> {
+    let params = match list.pop()? {
+        Val::Nil => Params::default(),
+        Val::Symbol(s) => Params {
+            required: vec![Pattern::Symbol(s.clone())],
+            rest: None,
+        },
+        x @ (Val::List(_, _) | Val::Vector(_, _)) => read_params(x.unwrap_seq()?)?,
+        _ => return rerr("expected an argument list"),
+    };
     let form = list.pop()?;
-    Ok(Function::define(args, envt, form).into())
+    Ok(Clause { params, form })
+}
+
+/// Reads a parameter list of binding patterns, where `& rest` collects any
+/// remaining arguments.
+fn read_params(forms: Vec<Val>) -> Result<Params, MalErr> {
+    let parts = SeqParts::read(forms)?;
+    if parts.all.is_some() {
+        return rerr(":as is not allowed in a parameter list");
+    }
+    Ok(Params {
+        required: parts.items,
+        rest: parts.rest,
+    })
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::read::read_str;
+
+    pub fn rep(envt: &Arc<Env>, src: &str) -> Res {
+        eval(envt, read_str(src)?)
+    }
+
+    /// Evaluates `src`, which must succeed, and prints the result.
+    pub fn show(envt: &Arc<Env>, src: &str) -> String {
+        format!("{}", rep(envt, src).unwrap())
+    }
+
+    #[test]
+    fn deep_tail_calls() {
+        let envt = Env::default();
+        rep(
+            &envt,
+            "(def! sum (fn* (n acc) (if (= n 0) acc (sum (- n 1) (+ n acc)))))",
+        )
+        .unwrap();
+        assert_eq!(rep(&envt, "(sum 100000 0)").unwrap(), Val::Int(5000050000));
+    }
+
+    #[test]
+    fn rest_params() {
+        let envt = Env::default();
+        rep(&envt, "(def! f (fn* (a & more) (list a more)))").unwrap();
+        assert_eq!(show(&envt, "(f 1 2 3)"), "(1 (2 3))");
+        assert_eq!(show(&envt, "(f 1)"), "(1 ())");
+
+        let e = rep(&envt, "(f)").unwrap_err();
+        assert_eq!(
+            e.msg,
+            "wrong number of arguments to f: expected at least 1, got 0"
+        );
+        let e = rep(&envt, "((fn* (a) a) 1 2)").unwrap_err();
+        assert_eq!(
+            e.msg,
+            "wrong number of arguments to anonymous function: expected 1, got 2"
+        );
+    }
+
+    #[test]
+    fn multi_arity() {
+        let envt = Env::default();
+        rep(
+            &envt,
+            "(def! f (fn* ([] 0) ([a] (list a)) ([a b & more] (list a b more))))",
+        )
+        .unwrap();
+        assert_eq!(show(&envt, "(f)"), "0");
+        assert_eq!(show(&envt, "(f 1)"), "(1)");
+        assert_eq!(show(&envt, "(f 1 2)"), "(1 2 ())");
+        assert_eq!(show(&envt, "(f 1 2 3)"), "(1 2 (3))");
+
+        rep(&envt, "(def! g (fn* ([a] a) ([a b] b)))").unwrap();
+        let e = rep(&envt, "(g)").unwrap_err();
+        assert_eq!(
+            e.msg,
+            "wrong number of arguments to g: expected 1 or 2, got 0"
+        );
+        assert!(rep(&envt, "(fn* ([a] a) ([b] b))").is_err());
+
+        // A single clause whose parameter list or body starts with a list.
+        assert_eq!(show(&envt, "((fn* ([x]) ((fn* [] x))) [5])"), "5");
+        rep(&envt, "(def! fs {1 (fn* [x] (* x 10))})").unwrap();
+        assert_eq!(show(&envt, "((fn* ([a b]) ((get fs a) b)) [1 2])"), "20");
+    }
+
+    #[test]
+    fn destructuring() {
+        let envt = Env::default();
+        assert_eq!(
+            show(&envt, "(let* [[a b & more] [1 2 3 4]] (list a b more))"),
+            "(1 2 (3 4))"
+        );
+        assert_eq!(
+            show(&envt, "(let* [[a [b c]] (list 1 [2 3])] (list a b c))"),
+            "(1 2 3)"
+        );
+        assert_eq!(show(&envt, "(let* [[a b] [1]] (list a b))"), "(1 nil)");
+        assert_eq!(show(&envt, "(let* [[a :as all] [1 2]] all)"), "[1 2]");
+        assert_eq!(
+            show(&envt, "(let* [{:keys [x y] :or {y 0}} {:x 1}] (list x y))"),
+            "(1 0)"
+        );
+        assert_eq!(
+            show(&envt, "(let* [{a :a b :b} {:a 1 :b 2}] (list a b))"),
+            "(1 2)"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "((fn* [[a b] {:keys [c]}] (list a b c)) [1 2] {:c 3})"
+            ),
+            "(1 2 3)"
+        );
+
+        let e = rep(&envt, "(let* [[a b] 5] a)").unwrap_err();
+        assert_eq!(e.msg, "cannot destructure 5 with sequential pattern [a b]");
+        let e = rep(&envt, "(let* [{:keys [x]} [1]] x)").unwrap_err();
+        assert_eq!(e.msg, "cannot destructure [1] with map pattern {:keys [x]}");
+        let e = rep(&envt, "(let* [5 5] 5)").unwrap_err();
+        assert_eq!(e.msg, "invalid binding pattern: 5");
+    }
+
+    #[test]
+    fn counts_evals() {
+        let envt = Env::default();
+        rep(&envt, "(def! f (fn* (n) (if (= n 0) 0 (f (- n 1)))))").unwrap();
+        let before = eval_count();
+        rep(&envt, "(f 100)").unwrap();
+        // Other tests may be evaluating at the same time, so this is
+        // only a lower bound: each call evaluates at least `(f ...)`,
+        // `(if ...)`, `(= n 0)` and `(- n 1)`.
+        assert!(eval_count() - before >= 400);
+    }
+
+    #[test]
+    fn docstrings() {
+        let envt = Env::default();
+        let describe = |src: &str| rep(&envt, src).unwrap().unwrap_func().unwrap().describe();
+
+        rep(&envt, "(def! inc \"Adds one to x.\" (fn* (x) (+ x 1)))").unwrap();
+        assert_eq!(describe("inc"), "(inc x)\n  Adds one to x.");
+        assert_eq!(show(&envt, "(inc 1)"), "2");
+
+        rep(&envt, "(def! g (fn* \"Either.\" ([] 0) ([x] x)))").unwrap();
+        assert_eq!(describe("g"), "(g)\n(g x)\n  Either.");
+        assert_eq!(describe("(fn* (x) x)"), "(fn* x)\n  (no documentation)");
+        assert_eq!(show(&envt, "((fn* \"s\" [] \"s\"))"), "\"s\"");
+
+        rep(
+            &envt,
+            "(defmacro! m \"Quotes x.\" (fn* (x) (list 'quote x)))",
+        )
+        .unwrap();
+        assert_eq!(describe("m"), "(m x)\n  Quotes x.");
+        assert_eq!(describe("count"), describe("(do count)"));
+        assert!(describe("count").contains("(count coll)"));
+        assert!(rep(&envt, "(def! n \"A number.\" 1)").is_err());
+    }
 }
@@ -7,7 +7,9 @@ use tracing_subscriber::{
 pub mod env;
 pub mod error;
 pub mod eval;
+pub mod printer;
 pub mod read;
+pub mod span;
 pub mod types;
 
 pub use crate::error::MalErr;
@@ -0,0 +1,222 @@
+/*!
+Printing values, either readably (so the output reads back as the same
+value) or for display.
+
+`Display for Val` prints readably; `Printer` chooses explicitly.
+
+```text
+(pr-str "a\nb")  ; "\"a\\nb\""
+(str "a\nb")     ; "a
+                 ;  b"
+```
+*/
+use std::{
+    cell::RefCell,
+    fmt::{Display, Formatter},
+    ops::Deref,
+    sync::Arc,
+};
+
+use crate::{
+    types::{Atom, List},
+    Val,
+};
+
+thread_local! {
+    /// The atoms this thread is printing the values of, innermost last.
+    static PRINTING: RefCell<Vec<*const Atom>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Prints `val`, with strings escaped and quoted if `print_readably`.
+pub struct Printer<'a> {
+    pub val: &'a Val,
+    pub print_readably: bool,
+}
+
+/// The printed form of `val`.
+pub fn pr_str(val: &Val, print_readably: bool) -> String {
+    Printer {
+        val,
+        print_readably,
+    }
+    .to_string()
+}
+
+/// The printed forms of `vals`, joined by `sep`.
+pub fn pr_join<I>(vals: I, print_readably: bool, sep: &str) -> String
+where
+    I: IntoIterator<Item = Val>,
+{
+    vals.into_iter()
+        .map(|v| pr_str(&v, print_readably))
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+fn write_escaped(s: &str, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\n' => write!(f, "\\n")?,
+            '\\' => write!(f, "\\\\")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl Printer<'_> {
+    fn with<'b>(&self, val: &'b Val) -> Printer<'b> {
+        Printer {
+            val,
+            print_readably: self.print_readably,
+        }
+    }
+
+    fn write_seq<'v, I>(&self, vals: I, f: &mut Formatter<'_>) -> std::fmt::Result
+    where
+        I: IntoIterator<Item = &'v Val>,
+    {
+        let mut val_iter = vals.into_iter();
+        if let Some(val) = val_iter.next() {
+            write!(f, "{}", self.with(val))?;
+        }
+        for val in val_iter {
+            write!(f, " {}", self.with(val))?;
+        }
+        Ok(())
+    }
+}
+
+/**
+Writes a float so it reads back as the same float: always with a decimal
+point or an exponent (`2.0`, `1e300`), and as `##Inf`, `##-Inf` or
+`##NaN` when it has no digits.
+*/
+/// Writes `(atom x)`, or `(atom ...)` if `a` is already being printed,
+/// as it is when it holds itself.
+fn write_atom(p: &Printer<'_>, a: &Arc<Atom>, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let ptr = Arc::as_ptr(a);
+    if PRINTING.with(|atoms| atoms.borrow().contains(&ptr)) {
+        return write!(f, "(atom ...)");
+    }
+    PRINTING.with(|atoms| atoms.borrow_mut().push(ptr));
+    let res = write!(f, "(atom {})", p.with(&a.get()));
+    PRINTING.with(|atoms| atoms.borrow_mut().pop());
+    res
+}
+
+fn write_float(x: f64, f: &mut Formatter<'_>) -> std::fmt::Result {
+    if x.is_nan() {
+        write!(f, "##NaN")
+    } else if x.is_infinite() {
+        write!(f, "{}", if x > 0.0 { "##Inf" } else { "##-Inf" })
+    } else {
+        // `Debug` is the shortest text that parses back exactly, and
+        // marks integral floats as floats.
+        write!(f, "{:?}", x)
+    }
+}
+
+impl Display for Printer<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.val {
+            Val::String(s) if self.print_readably => write_escaped(s, f),
+            Val::String(s) => write!(f, "{}", s),
+            Val::List(list, _) => {
+                let mut v: Vec<Val> = Vec::new();
+                let mut node = list.clone();
+                while let List::Node { val, next } = node.deref() {
+                    v.push(val.clone());
+                    node = next.clone();
+                }
+                write!(f, "(")?;
+                self.write_seq(&v, f)?;
+                write!(f, ")")
+            }
+            Val::Vector(v, _) => {
+                write!(f, "[")?;
+                self.write_seq(v.iter(), f)?;
+                write!(f, "]")
+            }
+            Val::Map(m, _) => {
+                let v: Vec<Val> = m.iter().flat_map(|(k, v)| [k, v]).collect();
+                write!(f, "{{")?;
+                self.write_seq(&v, f)?;
+                write!(f, "}}")
+            }
+            Val::Atom(a) => write_atom(self, a, f),
+            Val::Nil => write!(f, "nil"),
+            Val::True => write!(f, "true"),
+            Val::False => write!(f, "false"),
+            Val::Int(n) => write!(f, "{}", n),
+            Val::BigInt(n) => write!(f, "{}", n),
+            Val::Ratio(r) => write!(f, "{}/{}", r.numer(), r.denom()),
+            Val::Float(x) => write_float(x.0, f),
+            Val::Symbol(s) => write!(f, "{}", s),
+            Val::Keyword(s) => write!(f, ":{}", s),
+            Val::Func(fun, _) => write!(f, "{}", fun),
+            Val::Env(e) => write!(f, "<environment in {}>", e.ns_name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
+
+    #[test]
+    fn printing() {
+        let envt = Env::default();
+        assert_eq!(show(&envt, r#""a\nb\\c""#), r#""a\nb\\c""#);
+        assert_eq!(show(&envt, r#"(count "a\nb\\c")"#), "5");
+        assert_eq!(show(&envt, r#"(str "a" 1 "b" :c nil)"#), r#""a1b:cnil""#);
+        assert_eq!(
+            show(&envt, r#"(pr-str "a" [1 "b"])"#),
+            r#""\"a\" [1 \"b\"]""#
+        );
+        assert_eq!(show(&envt, r#"(str [1 "b\n"])"#), r#""[1 b\n]""#);
+        assert_eq!(
+            show(&envt, r#"(read-string (pr-str "a\nb\\c"))"#),
+            r#""a\nb\\c""#
+        );
+        assert!(rep(&envt, r#""abc\q""#).is_err());
+        assert_eq!(
+            show(&envt, "(let* [a (atom nil)] (reset! a [1 a]))"),
+            "[1 (atom [1 (atom ...)])]"
+        );
+    }
+
+    #[test]
+    fn floats_read_back() {
+        let envt = Env::default();
+        assert_eq!(
+            show(&envt, "[2.0 -0.0 0.1 1e300 1e-7 ##Inf ##-Inf]"),
+            "[2.0 -0.0 0.1 1e300 1e-7 ##Inf ##-Inf]"
+        );
+        assert_eq!(
+            show(&envt, "(str 2.0 \" \" (- ##Inf ##Inf))"),
+            "\"2.0 ##NaN\""
+        );
+        for x in [
+            "2.0",
+            "-0.0",
+            "1e300",
+            "0.1",
+            "123456789012345680.0",
+            "##Inf",
+            "##-Inf",
+        ] {
+            let src = format!("(let* [x {}] (= x (read-string (pr-str x))))", x);
+            assert_eq!(show(&envt, &src), "true", "{}", x);
+            let src = format!("(float? (read-string (pr-str {})))", x);
+            assert_eq!(show(&envt, &src), "true", "{}", x);
+        }
+        assert_eq!(show(&envt, "(float? (read-string (pr-str ##NaN)))"), "true");
+        assert_eq!(show(&envt, "(let* [inf 1] inf)"), "1");
+    }
+}
@@ -1,29 +1,44 @@
 /*!
 Reading input for the interpreter.
 */
-use std::sync::{
-    mpsc::{channel, Receiver, Sender},
-    Arc,
+use std::{
+    collections::VecDeque,
+    io::{BufRead, Read},
+    path::PathBuf,
+    sync::Arc,
 };
 
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
 use once_cell::sync::Lazy;
-use regex::{bytes, Regex};
+use regex::Regex;
+use rustyline::history::FileHistory;
 use tracing::{event, instrument, Level};
 
 use crate::{
     env::Env,
-    error::rerr,
-    eval::eval,
+    error::{err, rerr},
+    eval::{eval, eval_forms},
+    span::{self, Span},
     types::{List, Map},
     MalErr, Res, Val,
 };
 
+mod command;
+mod helper;
+use command::Flow;
+use helper::MalHelper;
+
 static TOKENIZER: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\.|[^\"])*"?|;.*|[^\s\[\]{}('",;)]*)"#)
+    Regex::new(r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#)
         .expect("unable to init tokenizing regex")
 });
-static ESCAPER: Lazy<bytes::Regex> =
-    Lazy::new(|| bytes::Regex::new(r#"\\"#).expect("unable to init escaping regex"));
+
+/// An integer, or a ratio of integers.
+static RATIONAL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^([+-]?[0-9]+)(?:/([0-9]+))?$").expect("unable to init number regex")
+});
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
@@ -34,6 +49,11 @@ pub enum Token {
     CloseBracket,
     CloseBrace,
     SingleQuote,
+    Backtick,
+    Tilde,
+    TildeAt,
+    At,
+    Caret,
     Comment(String),
     Obj(String),
 }
@@ -48,6 +68,11 @@ impl From<&str> for Token {
             "{" => Token::OpenBrace,
             "}" => Token::CloseBrace,
             "'" => Token::SingleQuote,
+            "`" => Token::Backtick,
+            "~" => Token::Tilde,
+            "~@" => Token::TildeAt,
+            "@" => Token::At,
+            "^" => Token::Caret,
             other => {
                 if other.as_bytes().first() == Some(&b';') {
                     Token::Comment(other.to_string())
@@ -59,100 +84,409 @@ impl From<&str> for Token {
     }
 }
 
-#[derive(Debug)]
-pub struct Tokenizer {
-    input: rustyline::DefaultEditor,
-    output: Sender<Token>,
+/**
+Where a `Reader` gets its input, one line at a time. Lines are only read
+when the `Reader` needs more tokens, so a prompt isn't printed before the
+previous form's result. A string literal may span lines: the `Reader`
+reads on until it's closed.
+*/
+pub trait Source {
+    /// The next line of input, or `None` at the end.
+    fn read_line(&mut self) -> Result<Option<String>, MalErr>;
 }
 
-impl Tokenizer {
-    #[instrument]
-    pub fn read_line(&mut self) {
-        match self.input.readline("user> ") {
-            Ok(line) => self.tokenize(line),
-            Err(e) => {
-                println!("exiting: {}", &e);
-                std::process::exit(0);
+/// The lines of any `BufRead`, like a file or standard input.
+pub struct Lines<R>(pub R);
+
+impl<R: BufRead> Source for Lines<R> {
+    fn read_line(&mut self) -> Result<Option<String>, MalErr> {
+        let mut line = String::new();
+        match self.0.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(line)),
+            Err(e) => rerr(format!("unable to read input: {}", &e)),
+        }
+    }
+}
+
+/**
+Entries typed at the terminal, with line editing.
+
+An entry with unclosed delimiters or an unclosed string continues on the
+next line, after a `  ... ` prompt, and goes into the history as a whole.
+Ctrl-C throws away the entry typed so far; Ctrl-D ends the input.
+*/
+pub struct Prompt {
+    editor: rustyline::Editor<MalHelper, FileHistory>,
+    continuation: bool,
+    /// Where to save entries, if anywhere.
+    history: Option<PathBuf>,
+}
+
+impl Prompt {
+    pub fn new() -> Result<Prompt, MalErr> {
+        use rustyline::{config::EditMode, CompletionType, Editor};
+
+        let rl_conf = rustyline::Config::builder()
+            .auto_add_history(false)
+            .edit_mode(EditMode::Emacs)
+            .completion_type(CompletionType::List)
+            .build();
+        match Editor::with_config(rl_conf) {
+            Ok(mut editor) => {
+                editor.set_helper(Some(MalHelper::new()));
+                Ok(Prompt {
+                    editor,
+                    continuation: true,
+                    history: None,
+                })
             }
+            Err(e) => rerr(format!("unable to start line editor: {}", &e)),
+        }
+    }
+
+    /// Makes every line a whole entry, even with unclosed delimiters, so
+    /// the `Reader` reports them.
+    pub fn single_line(self) -> Prompt {
+        Prompt {
+            continuation: false,
+            ..self
         }
     }
 
-    fn tokenize(&mut self, line: String) {
-        for tok in TOKENIZER.captures_iter(&line).filter_map(|t| {
-            match t.get(1).map(|m| m.as_str().trim()) {
-                None | Some("") => None,
-                Some(s) => Some(Token::from(s)),
+    /// Loads the history in `path`, if there is any, and saves new
+    /// entries there.
+    pub fn with_history<P: Into<PathBuf>>(mut self, path: P) -> Prompt {
+        let path = path.into();
+        let _ = self.editor.load_history(&path);
+        self.history = Some(path);
+        self
+    }
+
+    /// Completes and highlights the names bound in `envt`.
+    pub fn completing(mut self, envt: &Arc<Env>) -> Prompt {
+        self.set_env(envt);
+        self
+    }
+
+    /// Switches the `Env` whose names are completed and highlighted.
+    pub fn set_env(&mut self, envt: &Arc<Env>) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.envt = Some(envt.clone());
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        if entry.trim().is_empty() {
+            return;
+        }
+        let _ = self.editor.add_history_entry(entry);
+        if let Some(path) = &self.history {
+            if let Err(e) = self.editor.append_history(path) {
+                eprintln!("unable to save history to {:?}: {}", path, &e);
+                self.history = None;
+            }
+        }
+    }
+}
+
+impl Source for Prompt {
+    fn read_line(&mut self) -> Result<Option<String>, MalErr> {
+        use rustyline::error::ReadlineError;
+
+        let mut lines: Vec<String> = Vec::new();
+        loop {
+            let prompt = match self.editor.helper().and_then(|h| h.envt.as_ref()) {
+                _ if !lines.is_empty() => "  ... ".to_string(),
+                Some(envt) => format!("{}> ", envt.ns_name()),
+                None => "user> ".to_string(),
+            };
+            match self.editor.readline(&prompt) {
+                Ok(line) => {
+                    lines.push(line);
+                    let entry = lines.join("\n");
+                    if self.continuation && is_incomplete(&entry) {
+                        continue;
+                    }
+                    self.add_history(&entry);
+                    return Ok(Some(entry));
+                }
+                Err(ReadlineError::Interrupted) => lines.clear(),
+                Err(ReadlineError::Eof) if lines.is_empty() => return Ok(None),
+                // Let the `Reader` complain about the unfinished entry.
+                Err(ReadlineError::Eof) => return Ok(Some(lines.join("\n"))),
+                Err(e) => return rerr(format!("unable to read input: {}", &e)),
             }
-        }) {
-            if !matches!(&tok, &Token::Comment(_)) {
-                self.output.send(tok).unwrap();
+        }
+    }
+}
+
+/// Whether `tok` starts a string literal but doesn't finish it.
+fn is_open_string(tok: &str) -> bool {
+    let mut chars = match tok.strip_prefix('"') {
+        Some(rest) => rest.chars(),
+        None => return false,
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
             }
+            '"' => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Whether the last token of `text` is an unclosed string.
+fn ends_in_open_string(text: &str) -> bool {
+    matches!(
+        tokenize(text, &Arc::from(""), 1).last(),
+        Some(Lexeme { tok: Token::Obj(s), .. }) if is_open_string(s)
+    )
+}
+
+/// Whether `text` has more opening delimiters than closing ones, or ends
+/// inside a string.
+fn is_incomplete(text: &str) -> bool {
+    let mut depth: i64 = 0;
+    for lexeme in tokenize(text, &Arc::from(""), 1) {
+        match &lexeme.tok {
+            Token::OpenParen | Token::OpenBracket | Token::OpenBrace => depth += 1,
+            Token::CloseParen | Token::CloseBracket | Token::CloseBrace => depth -= 1,
+            Token::Obj(s) if is_open_string(s) => return true,
+            _ => {}
         }
     }
+    depth > 0
 }
 
+/// A `Token` and where it starts.
 #[derive(Debug)]
+struct Lexeme {
+    tok: Token,
+    span: Span,
+}
+
+/// The line of `text` that starts at byte `start`.
+fn line_at(text: &str, start: usize) -> Arc<str> {
+    text[start..].lines().next().unwrap_or("").into()
+}
+
+/**
+Splits `text` into `Lexeme`s, dropping comments. `text` is from `file`,
+starting at line `first_line`; it may hold several lines.
+*/
+fn tokenize(text: &str, file: &Arc<str>, first_line: usize) -> Vec<Lexeme> {
+    let mut lexemes: Vec<Lexeme> = Vec::new();
+    let mut line = first_line;
+    let mut line_start = 0;
+    let mut line_text = line_at(text, 0);
+    let mut scanned = 0;
+
+    for cap in TOKENIZER.captures_iter(text) {
+        let m = match cap.get(1) {
+            Some(m) if !m.as_str().trim().is_empty() => m,
+            _ => continue,
+        };
+
+        let newlines: Vec<usize> = text[scanned..m.start()]
+            .match_indices('\n')
+            .map(|(i, _)| scanned + i + 1)
+            .collect();
+        if let Some(&start) = newlines.last() {
+            line += newlines.len();
+            line_start = start;
+            line_text = line_at(text, start);
+        }
+        scanned = m.start();
+
+        let tok = Token::from(m.as_str().trim());
+        if matches!(tok, Token::Comment(_)) {
+            continue;
+        }
+        lexemes.push(Lexeme {
+            tok,
+            span: Span {
+                file: file.clone(),
+                line,
+                col: text[line_start..m.start()].chars().count() + 1,
+                text: line_text.clone(),
+            },
+        });
+    }
+
+    lexemes
+}
+
 pub struct Reader {
-    input: Receiver<Token>,
-    current: Option<Token>,
+    source: Option<Box<dyn Source>>,
+    /// What the source is called in spans: a path, `<repl>`, etc.
+    name: Arc<str>,
+    /// How many lines have been read from `source`.
+    lines: usize,
+    tokens: VecDeque<Lexeme>,
 }
 
 impl Reader {
-    pub fn peek(&mut self) -> &Token {
-        if self.current.is_none() {
-            let tok = self.input.recv().unwrap();
-            let _ = self.current.insert(tok);
+    /// A `Reader` that reads lines from `source`, called `name`, as it
+    /// needs them.
+    pub fn new<S: Source + 'static>(name: &str, source: S) -> Reader {
+        Reader {
+            source: Some(Box::new(source)),
+            name: name.into(),
+            lines: 0,
+            tokens: VecDeque::new(),
         }
-        self.current.as_ref().unwrap()
     }
 
-    pub fn next(&mut self) -> Token {
-        match self.current.take() {
-            Some(t) => t,
-            None => self.input.recv().unwrap(),
+    /// A `Reader` over the tokens of `text` and nothing else.
+    pub fn for_text(text: &str) -> Reader {
+        Reader::for_named_text("<string>", text, 1)
+    }
+
+    /// A `Reader` over the tokens of `text`, which starts at line
+    /// `first_line` of the source `name`.
+    pub fn for_named_text(name: &str, text: &str, first_line: usize) -> Reader {
+        let name: Arc<str> = name.into();
+        Reader {
+            source: None,
+            tokens: tokenize(text, &name, first_line).into(),
+            name,
+            lines: 0,
         }
     }
 
-    #[instrument]
+    /// Whether every token has been read and the source has run out.
+    pub fn at_end(&self) -> bool {
+        self.source.is_none() && self.tokens.is_empty()
+    }
+
+    /// Reads lines until there's a token to read; `false` if the source
+    /// runs out first.
+    fn fill(&mut self) -> Result<bool, MalErr> {
+        while self.tokens.is_empty() {
+            let mut text = match self.next_line()? {
+                Some(line) => line,
+                None => return Ok(false),
+            };
+            let first_line = self.lines;
+            // Tokenize a string that goes on to later lines as a whole.
+            while ends_in_open_string(&text) {
+                match self.next_line()? {
+                    Some(line) => text.push_str(&line),
+                    None => break,
+                }
+            }
+            self.tokens.extend(tokenize(&text, &self.name, first_line));
+        }
+        Ok(true)
+    }
+
+    /// The next line of the source, if there's any left.
+    fn next_line(&mut self) -> Result<Option<String>, MalErr> {
+        let line = match self.source.as_mut() {
+            Some(source) => source.read_line()?,
+            None => None,
+        };
+        match line {
+            Some(_) => self.lines += 1,
+            None => self.source = None,
+        }
+        Ok(line)
+    }
+
+    pub fn peek(&mut self) -> Result<&Token, MalErr> {
+        if !self.fill()? {
+            return rerr("unexpected EOF");
+        }
+        Ok(&self.tokens.front().unwrap().tok)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Token, MalErr> {
+        Ok(self.next_lexeme()?.tok)
+    }
+
+    fn next_lexeme(&mut self) -> Result<Lexeme, MalErr> {
+        if !self.fill()? {
+            return rerr("unexpected EOF");
+        }
+        Ok(self.tokens.pop_front().unwrap())
+    }
+
+    /**
+    Reads the next form, or `None` if the source ends cleanly before one
+    starts. The source ending inside a form is an "unexpected EOF" error,
+    as it is for `read_form`.
+    */
+    pub fn next_form(&mut self) -> Result<Option<Val>, MalErr> {
+        if self.fill()? {
+            self.read_form().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads the next form. Lists remember the span they were read from;
+    /// see `span::of`.
+    #[instrument(skip(self))]
     pub fn read_form(&mut self) -> Res {
-        let tok = self.next();
-        event!(Level::DEBUG, "next token: {:?}", &tok);
+        let Lexeme { tok, span } = self.next_lexeme()?;
+        event!(Level::DEBUG, "next token: {:?} at {}", &tok, &span);
 
         let val = match tok {
             Token::OpenParen => {
-                let mut vals = self.read_until(&Token::CloseParen)?;
-                let mut list = List::empty();
-                while let Some(val) = vals.pop() {
-                    list = list.cons(val);
-                }
-                Val::List(list)
+                let vals = self.read_until(&Token::CloseParen)?;
+                List::from_vec(vals).into()
             }
             Token::OpenBracket => {
                 let vals = self.read_until(&Token::CloseBracket)?;
                 Val::vec(vals)
             }
-            Token::OpenBrace => {
-                let map_arc = self.read_map()?;
-                Val::Map(map_arc)
-            }
+            Token::OpenBrace => self.read_map()?.into(),
             Token::Comment(_) => return Ok(Val::Nil), // This shouldn't happen.
-            Token::Obj(obj) => read_atom(obj)?,
-            Token::SingleQuote => {
-                let quoted = self.read_form()?;
-                Val::List(List::empty().cons(quoted).cons(Val::Symbol("quote".into())))
+            Token::Obj(obj) => {
+                read_atom(obj).map_err(|e| e.wrap_at("while reading", span.clone()))?
+            }
+            Token::SingleQuote => self.read_wrapped("quote")?,
+            Token::Backtick => self.read_wrapped("quasiquote")?,
+            Token::Tilde => self.read_wrapped("unquote")?,
+            Token::TildeAt => self.read_wrapped("splice-unquote")?,
+            Token::At => self.read_wrapped("deref")?,
+            Token::Caret => {
+                let meta = self.read_form()?;
+                let form = self.read_form()?;
+                List::from_vec(vec![Val::Symbol("with-meta".into()), form, meta]).into()
+            }
+            x => {
+                let e = err(format!("unexpected {:?}", &x));
+                return Err(e.wrap_at("while reading", span));
             }
-            x => return rerr(format!("unexpected {:?}", &x)),
         };
 
+        if let Val::List(list, _) = &val {
+            span::record(list, span);
+        }
         Ok(val)
     }
 
+    /// Reads the next form as `(name form)`, as the reader macros do.
+    fn read_wrapped(&mut self, name: &str) -> Res {
+        let form = self.read_form()?;
+        Ok(List::empty()
+            .cons(form)
+            .cons(Val::Symbol(name.into()))
+            .into())
+    }
+
     fn read_until(&mut self, zigamorph: &Token) -> Result<Vec<Val>, MalErr> {
         let mut vals: Vec<Val> = Vec::new();
 
         loop {
-            if self.peek() == zigamorph {
-                let _ = self.next();
+            if self.peek()? == zigamorph {
+                let _ = self.next()?;
                 return Ok(vals);
             }
             let val = self.read_form()?;
@@ -160,25 +494,89 @@ impl Reader {
         }
     }
 
-    fn read_map(&mut self) -> Result<Arc<Map>, MalErr> {
-        let map = Arc::new(Map::default());
+    fn read_map(&mut self) -> Result<Map, MalErr> {
+        let mut map = Map::default();
 
         loop {
-            if self.peek() == &Token::CloseBrace {
-                let _ = self.next();
+            if self.peek()? == &Token::CloseBrace {
+                let _ = self.next()?;
                 return Ok(map);
             }
             let key = self.read_form()?;
             let val = self.read_form()?;
-            let _ = map.insert(key, val)?;
+            map = map.assoc(key, val);
         }
     }
 }
 
+/// Reads the first form in `text`.
+pub fn read_str(text: &str) -> Res {
+    Reader::for_text(text).read_form()
+}
+
+/// Reads every form in `text`, which is the whole of the source `name`.
+pub fn read_all(name: &str, text: &str) -> Result<Vec<Val>, MalErr> {
+    let mut reader = Reader::for_named_text(name, text, 1);
+    let mut forms = Vec::new();
+    while let Some(form) = reader.next_form()? {
+        forms.push(form);
+    }
+    Ok(forms)
+}
+
+/// Reads every form in the file at `path`; see `eval_forms`.
+pub fn read_file(path: &str) -> Result<Vec<Val>, MalErr> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return rerr(format!("unable to read {:?}: {}", path, &e)),
+    };
+    read_all(path, &text)
+}
+
+/// Reads an integer too big for an `i64`, or a ratio like `-3/4`.
+fn read_number(obj: &str) -> Result<Option<Val>, MalErr> {
+    let caps = match RATIONAL.captures(obj) {
+        Some(caps) => caps,
+        None => return Ok(None),
+    };
+    let parse = |digits: &str| {
+        digits
+            .parse::<BigInt>()
+            .map_err(|e| err(format!("bad number {}: {}", obj, &e)))
+    };
+    let numer = parse(&caps[1])?;
+    match caps.get(2).map(|m| parse(m.as_str())).transpose()? {
+        None => Ok(Some(numer.into())),
+        Some(denom) if denom.is_zero() => rerr(format!("{} divides by zero", obj)),
+        Some(denom) => Ok(Some(BigRational::new(numer, denom).into())),
+    }
+}
+
+/**
+Reads a float in decimal or exponent form, or one of `##Inf`, `##-Inf`
+and `##NaN`. Names like `inf` and `nan` stay symbols.
+*/
+fn read_float(obj: &str) -> Option<f64> {
+    match obj {
+        "##Inf" => return Some(f64::INFINITY),
+        "##-Inf" => return Some(f64::NEG_INFINITY),
+        "##NaN" => return Some(f64::NAN),
+        _ => {}
+    }
+    let digits = obj.trim_start_matches(['+', '-']);
+    if digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        obj.parse().ok()
+    } else {
+        None
+    }
+}
+
 fn read_atom(obj: String) -> Result<Val, MalErr> {
     if let Ok(i) = obj.parse::<i64>() {
         return Ok(i.into());
-    } else if let Ok(x) = obj.parse::<f64>() {
+    } else if let Some(n) = read_number(&obj)? {
+        return Ok(n);
+    } else if let Some(x) = read_float(&obj) {
         return Ok(x.into());
     }
 
@@ -204,83 +602,318 @@ fn read_atom(obj: String) -> Result<Val, MalErr> {
     }
 }
 
+/**
+Reads a string literal, undoing the escapes `\"`, `\n` and `\\` that
+`printer` writes when printing readably. Returns `None` if `chars` isn't
+a string literal at all.
+*/
 fn make_string(chars: &str) -> Result<Option<String>, MalErr> {
-    let bytes = chars.as_bytes();
-    match bytes.first() {
+    let mut chars = match chars.strip_prefix('"') {
+        Some(rest) => rest.chars(),
         None => return Ok(None),
-        Some(b'"') => {}
-        Some(_) => return Ok(None),
+    };
+
+    let mut s = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(Some(s)),
+            '"' => return rerr("unbalanced string"),
+            '\\' => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some(c @ ('"' | '\\')) => s.push(c),
+                Some(c) => return rerr(format!("unknown escape sequence \\{} in string", c)),
+                None => break,
+            },
+            c => s.push(c),
+        }
     }
 
-    if bytes.len() < 2 || bytes.last() != Some(&b'"') {
-        return rerr("unbalanced string");
+    rerr("unbalanced string")
+}
+
+/// The next entry from `source`, or `None` when it runs out (or fails).
+fn next_entry<S: Source>(source: &mut S) -> Option<String> {
+    match source.read_line() {
+        Ok(entry) => entry,
+        Err(e) => {
+            println!("{}", &e);
+            None
+        }
     }
+}
 
-    let sub_bytes = &bytes[1..(bytes.len() - 1)];
-    let mut unescape = false;
+/**
+Reads each form in `entry`, which starts at line `first_line` of the
+REPL's input, passes it to `f`, and prints the result.
 
-    for w in sub_bytes.windows(2) {
-        // Safety: We are looking at windows of length 2; w[0] and
-        // w[1] are guaranteed to be in-bounds.
-        let (a, b) = unsafe { (w.get_unchecked(0), w.get_unchecked(1)) };
-        if b == &b'"' {
-            if a == &b'\\' {
-                unescape = true;
+A form left unclosed at the end of `entry` is an "unexpected EOF" error,
+and an error skips the rest of `entry`.
+*/
+fn print_forms<F>(entry: &str, first_line: usize, f: F)
+where
+    F: Fn(Val) -> Res,
+{
+    let mut reader = Reader::for_named_text("<repl>", entry, first_line);
+    loop {
+        match reader.next_form().and_then(|form| form.map(&f).transpose()) {
+            Ok(Some(val)) => println!("{}", &val),
+            Ok(None) => break,
+            Err(e) => {
+                println!("{}", &e);
                 break;
-            } else {
-                return rerr("unbalanced string");
             }
         }
     }
+}
 
-    if !unescape {
-        //
-        // TODO: Make this an unsafe conversion.
-        //
-        let s = String::from_utf8(sub_bytes.to_vec()).unwrap();
-        return Ok(Some(s));
+/// Reads entries from `source` and passes each form in them to `f`
+/// (usually `eval`), printing each result, until `source` runs out.
+pub fn repl<S, F>(source: &mut S, f: F)
+where
+    S: Source,
+    F: Fn(Val) -> Res,
+{
+    let mut lines = 0;
+    while let Some(entry) = next_entry(source) {
+        print_forms(&entry, lines + 1, &f);
+        lines += entry.lines().count().max(1);
     }
+}
+
+/**
+Runs the interactive REPL, which also takes the commands in `command`.
 
-    let matches: Vec<&[u8]> = ESCAPER.split(sub_bytes).collect();
-    let v: Vec<u8> = matches.join(&b'"');
-    //
-    // TODO: Make this an unsafe conversion.
-    //
-    let s = String::from_utf8(v).unwrap();
+History is kept in the file named by the `MAL_HISTORY` environment
+variable, or `.mal-history` if it isn't set; set it empty to keep none.
+*/
+pub fn run() {
+    let mut envt = Env::default();
+    let history = std::env::var("MAL_HISTORY").unwrap_or_else(|_| ".mal-history".into());
+    let prompt = match Prompt::new() {
+        Ok(prompt) => prompt.completing(&envt),
+        Err(e) => {
+            eprintln!("{}", &e);
+            std::process::exit(1);
+        }
+    };
+    let mut prompt = match history.as_str() {
+        "" => prompt,
+        path => prompt.with_history(path),
+    };
 
-    Ok(Some(s))
+    let mut lines = 0;
+    while let Some(entry) = next_entry(&mut prompt) {
+        match command::run(&entry, &mut envt, &mut prompt) {
+            Some(Flow::Quit) => return,
+            Some(Flow::Continue) => {}
+            None => print_forms(&entry, lines + 1, |v| eval(&envt.current_ns(), v)),
+        }
+        // Follow any `ns` form, so the next entry is in that namespace.
+        let current = envt.current_ns();
+        if !Arc::ptr_eq(&envt, &current) {
+            envt = current;
+            prompt.set_env(&envt);
+        }
+        lines += entry.lines().count().max(1);
+    }
 }
 
-pub fn run() {
-    use rustyline::{config::EditMode, DefaultEditor};
+/// A default `Env` with `*ARGV*` bound to `argv`.
+fn env_with_argv<I>(argv: I) -> Arc<Env>
+where
+    I: IntoIterator<Item = String>,
+{
+    let envt = Env::default();
+    let argv: Vec<Val> = argv.into_iter().map(Val::from).collect();
+    envt.set("*ARGV*", List::from_vec(argv).into());
+    envt
+}
 
-    let rl_conf = rustyline::Config::builder()
-        .auto_add_history(true)
-        .edit_mode(EditMode::Emacs)
-        .build();
-    let rl = DefaultEditor::with_config(rl_conf).unwrap();
+/// Evaluates the file at `path` with `*ARGV*` bound to `argv`.
+pub fn run_file<I>(path: &str, argv: I) -> Res
+where
+    I: IntoIterator<Item = String>,
+{
+    let envt = env_with_argv(argv);
+    eval_forms(&envt, read_file(path)?)
+}
 
-    let (tx, rx) = channel::<Token>();
+/// Evaluates each form in `text`, from the source `name`, with `*ARGV*`
+/// bound to `argv`; returns the value of the last one.
+pub fn run_text<I>(name: &str, text: &str, argv: I) -> Res
+where
+    I: IntoIterator<Item = String>,
+{
+    let envt = env_with_argv(argv);
+    eval_forms(&envt, read_all(name, text)?)
+}
 
-    let mut tokenizer = Tokenizer {
-        input: rl,
-        output: tx,
+/**
+Runs what a command line (without the program name) asks for, and
+returns the exit status: 1 if evaluation failed, with the error trace on
+stderr, or 2 if the arguments don't make sense.
+
+```text
+step5_tco                    the REPL
+step5_tco file.mal a b       evaluate file.mal with *ARGV* ("a" "b")
+step5_tco - a b              evaluate standard input, likewise
+step5_tco -e '(+ 1 2)' a b   evaluate the forms given and print the last
+```
+*/
+pub fn run_args<I>(args: I) -> i32
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let res = match args.next().as_deref() {
+        None => {
+            run();
+            return 0;
+        }
+        Some("-") => {
+            let mut text = String::new();
+            match std::io::stdin().read_to_string(&mut text) {
+                Ok(_) => run_text("<stdin>", &text, args),
+                Err(e) => rerr(format!("unable to read standard input: {}", &e)),
+            }
+        }
+        Some("-e") => match args.next() {
+            Some(text) => run_text("<-e>", &text, args).inspect(|val| println!("{}", val)),
+            None => {
+                eprintln!("-e requires an expression to evaluate");
+                return 2;
+            }
+        },
+        Some(path) => run_file(path, args),
     };
 
-    let mut reader = Reader {
-        input: rx,
-        current: None,
-    };
+    match res {
+        Ok(_) => 0,
+        Err(e) => {
+            eprint!("{}", &e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_from_any_source() {
+        let text = "(+ 1\n   2) [3\n 4]\n(unclosed\n";
+        let mut reader = Reader::new("<test>", Lines(text.as_bytes()));
+        assert_eq!(format!("{}", reader.read_form().unwrap()), "(+ 1 2)");
+        assert_eq!(format!("{}", reader.read_form().unwrap()), "[3 4]");
+        assert!(!reader.at_end());
+
+        let e = reader.read_form().unwrap_err();
+        assert!(format!("{}", &e).contains("unexpected EOF"));
+        assert!(reader.at_end());
+
+        let text = "(str \"a\n\\\"b\n\" 1)\n(x)";
+        let mut reader = Reader::new("<test>", Lines(text.as_bytes()));
+        let form = reader.read_form().unwrap();
+        assert_eq!(
+            form.unwrap_seq().unwrap()[1],
+            Val::String("a\n\"b\n".into())
+        );
+        let span = span::of(&reader.read_form().unwrap()).unwrap();
+        assert_eq!((span.line, span.col), (4, 1));
+    }
 
-    std::thread::spawn(move || loop {
-        tokenizer.read_line();
-    });
+    #[test]
+    fn clean_end_of_input() {
+        let text = "(+ 1\n   2)\n[3 4] ; done\n\n";
+        let mut reader = Reader::new("<test>", Lines(text.as_bytes()));
+        assert_eq!(
+            format!("{}", reader.next_form().unwrap().unwrap()),
+            "(+ 1 2)"
+        );
+        assert_eq!(format!("{}", reader.next_form().unwrap().unwrap()), "[3 4]");
+        assert!(!reader.at_end());
+        assert!(reader.next_form().unwrap().is_none());
+        assert!(reader.at_end());
+
+        let mut reader = Reader::new("<test>", Lines("[1 2".as_bytes()));
+        let e = reader.next_form().unwrap_err();
+        assert!(format!("{}", &e).contains("unexpected EOF"));
+        assert_eq!(read_all("<test>", "1 2 ; three").unwrap().len(), 2);
+    }
 
-    let envt = Env::default();
-    loop {
-        match reader.read_form().and_then(|v| eval(&envt, v)) {
-            Ok(val) => println!("{}", &val),
-            Err(e) => println!("{}", &e),
-        }
+    #[test]
+    fn read_str_errors() {
+        assert!(read_str("(1 2").is_err());
+        assert!(read_str("").is_err());
+        assert_eq!(format!("{}", read_str("{:a 1}").unwrap()), "{:a 1}");
+        // Only ASCII digits make a number.
+        assert!(matches!(read_str("\u{661}\u{662}"), Ok(Val::Symbol(_))));
+        assert!(matches!(read_str("1/\u{662}"), Ok(Val::Symbol(_))));
+    }
+
+    #[test]
+    fn incomplete_entries() {
+        assert!(is_incomplete("(def! f (fn* [x]"));
+        assert!(is_incomplete("{:a [1 2]"));
+        assert!(is_incomplete("(str \"a (b"));
+        assert!(is_incomplete("\"ends in a quote\\\""));
+        assert!(!is_incomplete("(str \"a (b\")"));
+        assert!(!is_incomplete("(+ 1 2)) ; (unbalanced"));
+        assert!(!is_incomplete(""));
+    }
+
+    #[test]
+    fn spans() {
+        let text = "(a\n  (b \"x\ny\" (c)))";
+        let outer = Reader::for_named_text("t.mal", text, 1)
+            .read_form()
+            .unwrap();
+        let span = span::of(&outer).unwrap();
+        assert_eq!((span.line, span.col), (1, 1));
+
+        let inner = outer.unwrap_seq().unwrap()[1].clone();
+        let span = span::of(&inner).unwrap();
+        assert_eq!(format!("{}", &span), "t.mal:2:3");
+        assert_eq!(span.caret(), "  ^");
+
+        let innermost = inner.unwrap_seq().unwrap()[2].clone();
+        assert_eq!(format!("{}", span::of(&innermost).unwrap()), "t.mal:3:4");
+    }
+
+    #[test]
+    fn reader_macros() {
+        let show = |src: &str| format!("{}", read_str(src).unwrap());
+        assert_eq!(
+            show("`(1 ~a ~@b)"),
+            "(quasiquote (1 (unquote a) (splice-unquote b)))"
+        );
+        assert_eq!(show("@a"), "(deref a)");
+        assert_eq!(show("^{:m 1} [x]"), "(with-meta [x] {:m 1})");
+        assert_eq!(show(r#""a\"b\\""#), r#""a\"b\\""#);
+        assert_eq!(show(r#"("x\\" y)"#), r#"("x\\" y)"#);
+    }
+
+    #[test]
+    fn run_text_with_argv() {
+        let argv = vec!["a".to_string(), "b".to_string()];
+        let val = run_text("<test>", "(def! n 2) (cons n *ARGV*)", argv).unwrap();
+        assert_eq!(format!("{}", val), "(2 \"a\" \"b\")");
+        assert!(run_text("<test>", "(nope)", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn traces_tail_forms() {
+        let text = "\n(def! f (fn* [x] (+ x :a)))\n(def! g (fn* [x] (f x)))\n\n(g 1)";
+        let e = run_text("t.mal", text, Vec::new()).unwrap_err();
+        let frames: Vec<String> = e
+            .context
+            .iter()
+            .map(|frame| format!("{} {}", frame.msg, frame.span.as_ref().unwrap()))
+            .collect();
+        assert_eq!(
+            frames,
+            vec!["in form (+ x :a) t.mal:2:18", "in form (g 1) t.mal:5:1"]
+        );
     }
 }
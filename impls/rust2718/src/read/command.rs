@@ -0,0 +1,124 @@
+/*!
+REPL commands: entries like `:doc map` that look at or manage the
+session instead of being evaluated.
+
+```text
+user> :time (apply + (map sq [1 2 3]))
+14
+;; 169.48µs, 22 evals
+```
+
+Only the names below are commands; any other entry, including one that
+starts with a keyword, is read as mal.
+*/
+use std::{sync::Arc, time::Instant};
+
+use super::{print_forms, read_file, Prompt};
+use crate::{
+    env::Env,
+    eval::{eval, eval_count, eval_forms, SPECIAL_FORMS},
+    printer::pr_str,
+    Val,
+};
+
+const HELP: &str = ";; :env [prefix]  list the bindings (starting with prefix)
+;; :doc name       describe what name is bound to, with its docstring
+;; :time form...   evaluate forms and report the time and evals taken
+;; :load path      evaluate the file at path
+;; :reset          start over with a fresh environment
+;; :quit           leave the REPL
+;; :help           show this";
+
+/// Longest printed value `:env` shows before cutting it short.
+const ENV_WIDTH: usize = 60;
+
+/// What the REPL should do after a command.
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/**
+Runs `entry` if it's a command, and says what to do next; `None` if it
+isn't a command. `:reset` replaces `envt`, and points `prompt`'s
+completion at the new one.
+*/
+pub fn run(entry: &str, envt: &mut Arc<Env>, prompt: &mut Prompt) -> Option<Flow> {
+    let entry = entry.trim();
+    let (cmd, arg) = match entry.split_once(char::is_whitespace) {
+        Some((cmd, arg)) => (cmd, arg.trim()),
+        None => (entry, ""),
+    };
+
+    match cmd {
+        ":env" => show_env(envt, arg),
+        ":doc" => show_doc(envt, arg),
+        ":time" => time(envt, arg),
+        ":load" => {
+            match read_file(arg).and_then(|forms| envt.in_current_ns(|ns| eval_forms(ns, forms))) {
+                Ok(_) => println!(";; loaded {}", arg),
+                Err(e) => println!("{}", &e),
+            }
+        }
+        ":reset" => {
+            envt.close();
+            *envt = Env::default();
+            prompt.set_env(envt);
+            println!(";; environment reset");
+        }
+        ":quit" => return Some(Flow::Quit),
+        ":help" => println!("{}", HELP),
+        _ => return None,
+    }
+    Some(Flow::Continue)
+}
+
+fn show_env(envt: &Arc<Env>, prefix: &str) {
+    for name in envt.keys().iter().filter(|k| k.starts_with(prefix)) {
+        let val = match envt.get(name) {
+            Ok(val) => pr_str(&val, true),
+            Err(_) => continue,
+        };
+        if val.chars().count() > ENV_WIDTH {
+            let short: String = val.chars().take(ENV_WIDTH - 3).collect();
+            println!("{}  {}...", name, short);
+        } else {
+            println!("{}  {}", name, val);
+        }
+    }
+}
+
+fn show_doc(envt: &Arc<Env>, name: &str) {
+    if SPECIAL_FORMS.contains(&name) {
+        println!(";; {} is a special form", name);
+        return;
+    }
+    let f = match envt.get(name) {
+        Ok(Val::Func(f, _)) => f,
+        Ok(val) => {
+            println!(";; {} is not a function: {}", name, pr_str(&val, true));
+            return;
+        }
+        Err(e) => {
+            println!("{}", &e);
+            return;
+        }
+    };
+
+    let kind = match f.as_function() {
+        Some(func) if func.is_macro() => "macro",
+        Some(_) => "function",
+        None => "builtin function",
+    };
+    println!(";; {} is a {}", name, kind);
+    for line in f.describe().lines() {
+        println!(";;   {}", line);
+    }
+}
+
+fn time(envt: &Arc<Env>, src: &str) {
+    let start = Instant::now();
+    let evals = eval_count();
+    print_forms(src, 1, |v| eval(envt, v));
+    println!(";; {:.2?}, {} evals", start.elapsed(), eval_count() - evals);
+}
@@ -0,0 +1,199 @@
+/*!
+Line editing help for the REPL: completing names bound in the `Env`,
+hinting from the history, and highlighting the line as it's typed.
+*/
+use std::{borrow::Cow, sync::Arc};
+
+use rustyline::{
+    completion::Completer,
+    highlight::Highlighter,
+    hint::{Hinter, HistoryHinter},
+    validate::Validator,
+    Context, Helper,
+};
+
+use super::{read_float, RATIONAL, TOKENIZER};
+use crate::{env::Env, eval::SPECIAL_FORMS, Val};
+
+const STRING: &str = "\x1b[32m";
+const KEYWORD: &str = "\x1b[36m";
+const NUMBER: &str = "\x1b[33m";
+const SPECIAL_FORM: &str = "\x1b[1;35m";
+const BUILTIN: &str = "\x1b[34m";
+const COMMENT: &str = "\x1b[2m";
+const MATCHED: &str = "\x1b[1;7m";
+const RESET: &str = "\x1b[0m";
+
+/// Characters that can't be part of a symbol.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}'\"`,;@^~".contains(c)
+}
+
+pub struct MalHelper {
+    /// Where to look up names to complete and highlight.
+    pub envt: Option<Arc<Env>>,
+    hinter: HistoryHinter,
+}
+
+impl MalHelper {
+    pub fn new() -> MalHelper {
+        MalHelper {
+            envt: None,
+            hinter: HistoryHinter {},
+        }
+    }
+
+    /// The color for the token `tok`, if it gets one.
+    fn color(&self, tok: &str) -> Option<&'static str> {
+        match tok.chars().next()? {
+            '"' => return Some(STRING),
+            ':' => return Some(KEYWORD),
+            ';' => return Some(COMMENT),
+            _ => {}
+        }
+        if RATIONAL.is_match(tok) || read_float(tok).is_some() {
+            Some(NUMBER)
+        } else if SPECIAL_FORMS.contains(&tok) {
+            Some(SPECIAL_FORM)
+        } else {
+            match self.envt.as_ref().map(|e| e.root().get(tok)) {
+                Some(Ok(Val::Func(f, _))) if f.as_function().is_none() => Some(BUILTIN),
+                _ => None,
+            }
+        }
+    }
+}
+
+/**
+The byte offsets of the bracket next to `pos` (under the cursor, or else
+just before it) and of the bracket it matches, if both exist.
+*/
+fn matching_brackets(line: &str, pos: usize) -> Option<(usize, usize)> {
+    let mut open: Vec<usize> = Vec::new();
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for cap in TOKENIZER.captures_iter(line) {
+        let m = match cap.get(1) {
+            Some(m) => m,
+            None => continue,
+        };
+        match m.as_str() {
+            "(" | "[" | "{" => open.push(m.start()),
+            ")" | "]" | "}" => {
+                if let Some(start) = open.pop() {
+                    pairs.push((start, m.start()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let at = |i: usize| pairs.iter().find(|(a, b)| *a == i || *b == i).copied();
+    at(pos).or_else(|| pos.checked_sub(1).and_then(at))
+}
+
+impl Completer for MalHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| is_delimiter(*c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let prefix = &line[start..pos];
+
+        let mut names: Vec<String> = SPECIAL_FORMS.iter().map(|s| s.to_string()).collect();
+        if let Some(envt) = &self.envt {
+            names.extend(envt.keys().iter().map(|s| s.to_string()));
+        }
+        names.retain(|s| s.starts_with(prefix));
+        names.sort();
+        names.dedup();
+        Ok((start, names))
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        self.hinter.hint(line, pos, ctx)
+    }
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let brackets = matching_brackets(line, pos);
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut done = 0;
+
+        for cap in TOKENIZER.captures_iter(line) {
+            let m = match cap.get(1) {
+                Some(m) if !m.as_str().is_empty() => m,
+                _ => continue,
+            };
+            out.push_str(&line[done..m.start()]);
+            let tok = m.as_str();
+            let color = match brackets {
+                Some((a, b)) if m.start() == a || m.start() == b => Some(MATCHED),
+                _ => self.color(tok),
+            };
+            match color {
+                Some(color) => {
+                    out.push_str(color);
+                    out.push_str(tok);
+                    out.push_str(RESET);
+                }
+                None => out.push_str(tok),
+            }
+            done = m.end();
+        }
+        out.push_str(&line[done..]);
+
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", COMMENT, hint, RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        // Any change can change the colors or move the cursor onto a
+        // bracket.
+        true
+    }
+}
+
+impl Validator for MalHelper {}
+
+impl Helper for MalHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn brackets() {
+        let line = "(let* [a \"(\"] (f a))";
+        assert_eq!(matching_brackets(line, 0), Some((0, 19)));
+        assert_eq!(matching_brackets(line, 13), Some((6, 12)));
+        assert_eq!(matching_brackets(line, 20), Some((0, 19)));
+        assert_eq!(matching_brackets(line, 3), None);
+    }
+
+    #[test]
+    fn colors() {
+        let mut helper = MalHelper::new();
+        helper.envt = Some(Env::default());
+        assert_eq!(helper.color("if"), Some(SPECIAL_FORM));
+        assert_eq!(helper.color("count"), Some(BUILTIN));
+        assert_eq!(helper.color("not"), None);
+        assert_eq!(helper.color(":k"), Some(KEYWORD));
+        assert_eq!(helper.color("\"s\""), Some(STRING));
+    }
+}
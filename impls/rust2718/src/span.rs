@@ -0,0 +1,87 @@
+/*!
+Where in the source a form was read from.
+
+The reader records the span of each list it reads in a side table keyed
+by the list's allocation, so spans survive being passed around (and
+`eval` can find them) without showing up as metadata.
+*/
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    sync::{Arc, Mutex, Weak},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{types::List, Val};
+
+/// A position in a named source: a file, `<repl>`, or `<string>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub file: Arc<str>,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, in characters.
+    pub col: usize,
+    /// The source line the span starts on, without its line ending.
+    pub text: Arc<str>,
+}
+
+impl Span {
+    /// A line with a caret under the span's column of `text`.
+    pub fn caret(&self) -> String {
+        // Keep tabs so the caret lines up however wide they're shown.
+        let indent: String = self
+            .text
+            .chars()
+            .take(self.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}^", indent)
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+struct Table {
+    spans: HashMap<usize, (Weak<List>, Span)>,
+    /// Drop entries for freed lists once the table grows to this size.
+    prune_at: usize,
+}
+
+static SPANS: Lazy<Mutex<Table>> = Lazy::new(|| {
+    Mutex::new(Table {
+        spans: HashMap::new(),
+        prune_at: 1024,
+    })
+});
+
+/// Records that `list` was read from `span`.
+pub fn record(list: &Arc<List>, span: Span) {
+    let mut table = SPANS.lock().unwrap();
+    if table.spans.len() >= table.prune_at {
+        table.spans.retain(|_, (list, _)| list.strong_count() > 0);
+        table.prune_at = (table.spans.len() * 2).max(1024);
+    }
+    let key = Arc::as_ptr(list) as usize;
+    table.spans.insert(key, (Arc::downgrade(list), span));
+}
+
+/// The span `val` was read from, if it's a list the reader recorded.
+pub fn of(val: &Val) -> Option<Span> {
+    let list = match val {
+        Val::List(list, _) => list,
+        _ => return None,
+    };
+    let table = SPANS.lock().unwrap();
+    match table.spans.get(&(Arc::as_ptr(list) as usize)) {
+        // The `Weak` keeps the allocation, so its address can't have
+        // been reused by another list while the entry exists.
+        Some((weak, span)) if weak.strong_count() > 0 => Some(span.clone()),
+        _ => None,
+    }
+}
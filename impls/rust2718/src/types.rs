@@ -2,23 +2,42 @@
 Types
 */
 use std::{
+    cmp::Ordering,
     convert::TryFrom,
     fmt::{Debug, Display, Formatter},
-    ops::Deref,
-    sync::{Arc, RwLock},
+    hash::{Hash, Hasher},
+    sync::Arc,
 };
 
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
 use ordered_float::OrderedFloat;
 
+mod atom;
 pub mod builtin;
 mod lambda;
 mod list;
 mod map;
-pub use lambda::{Builtin, Function, Lambda, StaticFunc};
+pub mod number;
+mod pattern;
+pub use atom::Atom;
+pub use lambda::{Builtin, BuiltinDef, Clause, EnvFunc, Function, Lambda, Params, StaticFunc};
 pub use list::List;
 pub use map::Map;
+pub use number::Num;
+pub use pattern::{Pattern, SeqParts};
 
-use crate::{error::rerr, MalErr};
+use crate::{env::Env, error::rerr, printer::Printer, MalErr};
+
+/// Metadata attached to a collection or function by `with-meta`.
+pub type Meta = Option<Arc<Val>>;
+
+/**
+The elements of a vector: a persistent RRB vector, so `conj` makes a new
+version in O(log n) that shares most of its structure with the old one.
+*/
+pub type Vector = im::Vector<Val>;
 
 #[derive(Clone, Debug)]
 pub enum Val {
@@ -26,14 +45,21 @@ pub enum Val {
     True,
     False,
     Int(i64),
+    /// An integer too big for an `Int`.
+    BigInt(Arc<BigInt>),
+    /// An exact fraction that isn't an integer, in lowest terms.
+    Ratio(Arc<BigRational>),
     Float(OrderedFloat<f64>),
     String(Arc<str>),
     Symbol(Arc<str>),
     Keyword(Arc<str>),
-    List(Arc<List>),
-    Vector(Arc<RwLock<Vec<Val>>>),
-    Map(Arc<Map>),
-    Func(Arc<dyn Lambda>),
+    List(Arc<List>, Meta),
+    Vector(Arc<Vector>, Meta),
+    Map(Arc<Map>, Meta),
+    Func(Arc<dyn Lambda>, Meta),
+    Atom(Arc<Atom>),
+    /// An environment, as `*ENV*` and `env-new` give.
+    Env(Arc<Env>),
 }
 
 impl Val {
@@ -41,7 +67,7 @@ impl Val {
     where
         Vec<Val>: From<V>,
     {
-        Val::Vector(Arc::new(RwLock::new(v.into())))
+        Val::Vector(Arc::new(Vector::from(Vec::from(v))), None)
     }
 
     pub fn unwrap_symbol(&self) -> Result<Arc<str>, MalErr> {
@@ -53,81 +79,160 @@ impl Val {
 
     pub fn unwrap_list(&self) -> Result<Arc<List>, MalErr> {
         match self {
-            Val::List(list) => Ok(list.clone()),
+            Val::List(list, _) => Ok(list.clone()),
             Val::Nil => Ok(List::empty()),
             _ => rerr("expected a list"),
         }
     }
 
+    /// The elements of a list, vector, or `nil`.
+    pub fn unwrap_seq(&self) -> Result<Vec<Val>, MalErr> {
+        match self {
+            Val::List(list, _) => {
+                let mut list = list.clone();
+                let mut v: Vec<Val> = Vec::new();
+                while let Some(val) = list.next() {
+                    v.push(val);
+                }
+                Ok(v)
+            }
+            Val::Vector(v, _) => Ok(v.iter().cloned().collect()),
+            Val::Nil => Ok(Vec::new()),
+            _ => rerr("expected a list or vector"),
+        }
+    }
+
     pub fn unwrap_func(&self) -> Result<Arc<dyn Lambda>, MalErr> {
         match self {
-            Val::Func(f) => Ok(f.clone()),
+            Val::Func(f, _) => Ok(f.clone()),
             _ => rerr("expected a function"),
         }
     }
-}
 
-impl Display for Val {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        use Val::*;
+    /**
+    The metadata attached to a collection or function, or `nil`.
 
+    A named interpreted function without attached metadata has
+    `{:name <its name>}`.
+    */
+    pub fn meta(&self) -> Val {
         match self {
-            Nil => write!(f, "nil"),
-            True => write!(f, "true"),
-            False => write!(f, "false"),
-            Int(n) => write!(f, "{}", &n),
-            Float(x) => write!(f, "{}", &x),
-            String(ref s) => write!(f, "\"{}\"", s),
-            Symbol(ref s) => write!(f, "{}", s),
-            Keyword(ref s) => write!(f, ":{}", s),
-            List(a) => write_list(a, f),
-            Vector(a) => write_vector(a, f),
-            Map(a) => write_map(a, f),
-            Func(fun) => write!(f, "{}", fun),
-        }
-    }
-}
-
-fn write_list(list: &Arc<List>, f: &mut Formatter) -> std::fmt::Result {
-    match list.deref() {
-        List::Nil => write!(f, "()"),
-        List::Node { ref next, ref val } => {
-            write!(f, "({}", val)?;
-            let mut node = next.clone();
-            while let List::Node { ref val, ref next } = node.deref() {
-                write!(f, " {}", val)?;
-                node = next.clone();
+            Val::List(_, Some(m))
+            | Val::Vector(_, Some(m))
+            | Val::Map(_, Some(m))
+            | Val::Func(_, Some(m)) => m.as_ref().clone(),
+            Val::Func(f, None) => match f.as_function().and_then(|f| f.name()) {
+                Some(name) => Map::default()
+                    .assoc(Val::Keyword("name".into()), Val::Symbol(name))
+                    .into(),
+                None => Val::Nil,
+            },
+            _ => Val::Nil,
+        }
+    }
+
+    /// This value with `meta` attached in place of any existing metadata.
+    pub fn with_meta(&self, meta: Val) -> Result<Val, MalErr> {
+        let m = Some(Arc::new(meta));
+        let v = match self {
+            Val::List(a, _) => Val::List(a.clone(), m),
+            Val::Vector(a, _) => Val::Vector(a.clone(), m),
+            Val::Map(a, _) => Val::Map(a.clone(), m),
+            Val::Func(a, _) => Val::Func(a.clone(), m),
+            _ => return rerr("metadata requires a collection or a function"),
+        };
+        Ok(v)
+    }
+
+    /**
+    Whether this is the very same value as `other`: scalars (numbers of the
+    same kind, strings, symbols, keywords) by value, and collections, atoms
+    and functions by identity, including their metadata. Never walks a
+    collection.
+    */
+    pub fn identical(&self, other: &Val) -> bool {
+        fn same<T: ?Sized>(a: &Arc<T>, b: &Arc<T>) -> bool {
+            Arc::ptr_eq(a, b)
+        }
+        fn same_meta(m: &Meta, n: &Meta) -> bool {
+            match (m, n) {
+                (Some(m), Some(n)) => same(m, n),
+                (m, n) => m.is_none() && n.is_none(),
             }
-            write!(f, ")")
+        }
+        match (self, other) {
+            (Val::Nil, Val::Nil) | (Val::True, Val::True) | (Val::False, Val::False) => true,
+            (Val::Int(n), Val::Int(m)) => n == m,
+            (Val::BigInt(n), Val::BigInt(m)) => n == m,
+            (Val::Ratio(r), Val::Ratio(s)) => r == s,
+            (Val::Float(x), Val::Float(y)) => x.to_bits() == y.to_bits(),
+            (Val::String(s), Val::String(t))
+            | (Val::Symbol(s), Val::Symbol(t))
+            | (Val::Keyword(s), Val::Keyword(t)) => s == t,
+            (Val::List(a, m), Val::List(b, n)) => same(a, b) && same_meta(m, n),
+            (Val::Vector(a, m), Val::Vector(b, n)) => same(a, b) && same_meta(m, n),
+            (Val::Map(a, m), Val::Map(b, n)) => same(a, b) && same_meta(m, n),
+            (Val::Func(f, m), Val::Func(g, n)) => same(f, g) && same_meta(m, n),
+            (Val::Atom(a), Val::Atom(b)) => same(a, b),
+            (Val::Env(e), Val::Env(f)) => same(e, f),
+            _ => false,
         }
     }
-}
 
-fn write_vector(v: &Arc<RwLock<Vec<Val>>>, f: &mut Formatter<'_>) -> std::fmt::Result {
-    write!(f, "[")?;
-    let handle = v.deref().read().unwrap();
-    let mut val_iter = handle.iter();
-    if let Some(val) = val_iter.next() {
-        write!(f, "{}", val)?;
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_)
+        )
     }
-    for val in val_iter {
-        write!(f, " {}", val)?;
+
+    /**
+    How this value orders against `other`. `nil` comes before anything
+    else and `false` before `true`; numbers compare by value, strings,
+    symbols and keywords by their text, and lists and vectors item by
+    item, a prefix first. Other pairs can't be compared.
+    */
+    pub fn compare(&self, other: &Val) -> Result<Ordering, MalErr> {
+        match (self, other) {
+            (Val::Nil, Val::Nil) => Ok(Ordering::Equal),
+            (Val::Nil, _) => Ok(Ordering::Less),
+            (_, Val::Nil) => Ok(Ordering::Greater),
+            (Val::True | Val::False, Val::True | Val::False) => {
+                Ok(matches!(self, Val::True).cmp(&matches!(other, Val::True)))
+            }
+            (a, b) if a.is_number() && b.is_number() => {
+                match number::compare(a.clone(), b.clone())? {
+                    Some(o) => Ok(o),
+                    // Only NaN is unordered; put it after the numbers.
+                    None => Ok(f64::try_from(a.clone())?.total_cmp(&f64::try_from(b.clone())?)),
+                }
+            }
+            (Val::String(s), Val::String(t))
+            | (Val::Symbol(s), Val::Symbol(t))
+            | (Val::Keyword(s), Val::Keyword(t)) => Ok(s.cmp(t)),
+            (Val::List(..) | Val::Vector(..), Val::List(..) | Val::Vector(..)) => {
+                let (u, v) = (self.unwrap_seq()?, other.unwrap_seq()?);
+                for (a, b) in u.iter().zip(&v) {
+                    match a.compare(b)? {
+                        Ordering::Equal => {}
+                        o => return Ok(o),
+                    }
+                }
+                Ok(u.len().cmp(&v.len()))
+            }
+            _ => rerr(format!("cannot compare {} with {}", self, other)),
+        }
     }
-    write!(f, "]")
 }
 
-fn write_map(m: &Arc<Map>, f: &mut Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{{")?;
-    let mut val_iter = m.iter();
-    if let Some((k, v)) = val_iter.next() {
-        let k: Val = k.into();
-        write!(f, "{} {}", k, v)?;
-    }
-    for (k, v) in val_iter {
-        let k: Val = k.into();
-        write!(f, " {} {}", k, v)?;
+impl Display for Val {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let p = Printer {
+            val: self,
+            print_readably: true,
+        };
+        write!(f, "{}", p)
     }
-    write!(f, "}}")
 }
 
 impl From<()> for Val {
@@ -152,6 +257,27 @@ impl From<i64> for Val {
     }
 }
 
+/// An `Int` if `n` fits in one.
+impl From<BigInt> for Val {
+    fn from(n: BigInt) -> Val {
+        match i64::try_from(&n) {
+            Ok(n) => Val::Int(n),
+            Err(_) => Val::BigInt(Arc::new(n)),
+        }
+    }
+}
+
+/// An integer if `r` is one.
+impl From<BigRational> for Val {
+    fn from(r: BigRational) -> Val {
+        if r.is_integer() {
+            r.to_integer().into()
+        } else {
+            Val::Ratio(Arc::new(r))
+        }
+    }
+}
+
 impl From<f64> for Val {
     fn from(x: f64) -> Val {
         Val::Float(OrderedFloat(x))
@@ -166,19 +292,31 @@ impl From<String> for Val {
 
 impl From<Arc<List>> for Val {
     fn from(a: Arc<List>) -> Val {
-        Val::List(a.clone())
+        Val::List(a.clone(), None)
     }
 }
 
 impl From<Arc<Map>> for Val {
     fn from(a: Arc<Map>) -> Val {
-        Val::Map(a.clone())
+        Val::Map(a.clone(), None)
+    }
+}
+
+impl From<Map> for Val {
+    fn from(m: Map) -> Val {
+        Val::Map(Arc::new(m), None)
+    }
+}
+
+impl From<Vector> for Val {
+    fn from(v: Vector) -> Val {
+        Val::Vector(Arc::new(v), None)
     }
 }
 
 impl From<Vec<Val>> for Val {
     fn from(v: Vec<Val>) -> Val {
-        Val::Vector(Arc::new(RwLock::new(v)))
+        Val::Vector(Arc::new(v.into()), None)
     }
 }
 
@@ -190,13 +328,13 @@ impl From<OrderedFloat<f64>> for Val {
 
 impl From<Builtin> for Val {
     fn from(b: Builtin) -> Val {
-        Val::Func(Arc::new(b))
+        Val::Func(Arc::new(b), None)
     }
 }
 
 impl From<Function> for Val {
     fn from(f: Function) -> Val {
-        Val::Func(Arc::new(f))
+        Val::Func(Arc::new(f), None)
     }
 }
 
@@ -206,27 +344,228 @@ impl TryFrom<Val> for f64 {
     fn try_from(v: Val) -> Result<f64, MalErr> {
         match v {
             Val::Int(n) => Ok(n as f64),
+            Val::BigInt(n) => Ok(n.to_f64().unwrap_or(f64::NAN)),
+            Val::Ratio(r) => Ok(r.to_f64().unwrap_or(f64::NAN)),
             Val::Float(x) => Ok(x.into()),
             _ => rerr("value cannot be converted to floating-point"),
         }
     }
 }
 
+/**
+Values are equal as `=` says: numbers by value whatever their kinds,
+lists and vectors by their items, and atoms and functions by identity.
+Metadata doesn't count.
+*/
 impl PartialEq for Val {
     fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Val {}
+
+/**
+A total order that agrees with `=`, so values can be map keys: by kind
+first (`nil`, booleans, numbers, strings, symbols, keywords, sequences,
+maps, atoms, functions, environments), then by value within a kind. NaN comes after
+the other numbers and, unlike with `==`, is equal to itself; atoms,
+functions and environments are ordered by address.
+*/
+impl Ord for Val {
+    fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Val::Nil, Val::Nil) => true,
-            (Val::True, Val::True) => true,
-            (Val::False, Val::False) => true,
-            (Val::Int(n), Val::Int(m)) => n == m,
-            (Val::Float(x), Val::Float(y)) => x == y,
-            (Val::String(s), Val::String(t)) => s == t,
-            (Val::Symbol(s), Val::Symbol(t)) => s == t,
-            (Val::Keyword(s), Val::Keyword(t)) => s == t,
-            (Val::List(a), Val::List(b)) => a == b,
-            (Val::Vector(u), Val::Vector(v)) => *u.read().unwrap() == *v.read().unwrap(),
-            (Val::Map(m), Val::Map(n)) => m == n,
-            _ => false,
+            (a, b) if a.is_number() && b.is_number() => {
+                match number::compare(a.clone(), b.clone()) {
+                    Ok(Some(o)) => o,
+                    _ => a.is_nan().cmp(&b.is_nan()),
+                }
+            }
+            (Val::String(s), Val::String(t))
+            | (Val::Symbol(s), Val::Symbol(t))
+            | (Val::Keyword(s), Val::Keyword(t)) => s.cmp(t),
+            // Shared structure is equal without looking inside.
+            (Val::List(a, _), Val::List(b, _)) if Arc::ptr_eq(a, b) => Ordering::Equal,
+            (Val::Vector(a, _), Val::Vector(b, _)) if Arc::ptr_eq(a, b) => Ordering::Equal,
+            (Val::Map(a, _), Val::Map(b, _)) if Arc::ptr_eq(a, b) => Ordering::Equal,
+            (Val::List(..) | Val::Vector(..), Val::List(..) | Val::Vector(..)) => {
+                self.items().cmp(other.items())
+            }
+            (Val::Map(m, _), Val::Map(n, _)) => m.iter().cmp(n.iter()),
+            (Val::Atom(a), Val::Atom(b)) => Arc::as_ptr(a).cmp(&Arc::as_ptr(b)),
+            (Val::Env(e), Val::Env(f)) => Arc::as_ptr(e).cmp(&Arc::as_ptr(f)),
+            (Val::Func(f, _), Val::Func(g, _)) => {
+                (Arc::as_ptr(f) as *const ()).cmp(&(Arc::as_ptr(g) as *const ()))
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Val {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Val {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_) => self.hash_number(state),
+            Val::String(s) | Val::Symbol(s) | Val::Keyword(s) => s.hash(state),
+            Val::List(..) | Val::Vector(..) => self.items().for_each(|v| v.hash(state)),
+            Val::Map(m, _) => m.iter().for_each(|e| e.hash(state)),
+            Val::Atom(a) => Arc::as_ptr(a).hash(state),
+            Val::Env(e) => Arc::as_ptr(e).hash(state),
+            Val::Func(f, _) => (Arc::as_ptr(f) as *const ()).hash(state),
+            Val::Nil | Val::True | Val::False => {}
+        }
+    }
+}
+
+impl Val {
+    /// Where this value's kind comes in the order of values.
+    fn rank(&self) -> u8 {
+        match self {
+            Val::Nil => 0,
+            Val::False => 1,
+            Val::True => 2,
+            Val::Int(_) | Val::BigInt(_) | Val::Ratio(_) | Val::Float(_) => 3,
+            Val::String(_) => 4,
+            Val::Symbol(_) => 5,
+            Val::Keyword(_) => 6,
+            Val::List(..) | Val::Vector(..) => 7,
+            Val::Map(..) => 8,
+            Val::Atom(_) => 9,
+            Val::Func(..) => 10,
+            Val::Env(_) => 11,
         }
     }
+
+    fn is_nan(&self) -> bool {
+        matches!(self, Val::Float(x) if x.is_nan())
+    }
+
+    /// The items of a list or vector, without copying them out; none for
+    /// anything else.
+    fn items(&self) -> Box<dyn Iterator<Item = Val> + '_> {
+        match self {
+            Val::List(list, _) => {
+                let mut list = list.clone();
+                Box::new(std::iter::from_fn(move || list.next()))
+            }
+            Val::Vector(v, _) => Box::new(v.iter().cloned()),
+            _ => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// Hashes a number by its exact value, so numbers that are `=` hash
+    /// alike whatever their kinds.
+    fn hash_number<H: Hasher>(&self, state: &mut H) {
+        let exact = match self {
+            Val::Int(n) => return n.hash(state),
+            Val::BigInt(n) => BigRational::from(n.as_ref().clone()),
+            Val::Ratio(r) => r.as_ref().clone(),
+            Val::Float(x) => match BigRational::from_float(x.0) {
+                Some(r) => r,
+                // NaN and the infinities aren't equal to any exact number.
+                None => return x.hash(state),
+            },
+            _ => return,
+        };
+        match Val::from(exact) {
+            Val::Int(n) => n.hash(state),
+            Val::BigInt(n) => n.hash(state),
+            Val::Ratio(r) => r.hash(state),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
+    use std::collections::hash_map::DefaultHasher;
+
+    use super::*;
+
+    fn hash(v: &Val) -> u64 {
+        let mut h = DefaultHasher::new();
+        v.hash(&mut h);
+        h.finish()
+    }
+
+    #[test]
+    fn equal_values_hash_alike() {
+        let half = BigRational::new(1.into(), 2.into());
+        let alike = [
+            (Val::Int(1), Val::Float(1.0.into())),
+            (Val::Ratio(Arc::new(half)), Val::Float(0.5.into())),
+            (Val::Float(0.0.into()), Val::Float((-0.0).into())),
+            (Val::Float(f64::NAN.into()), Val::Float(f64::NAN.into())),
+            (
+                List::from_vec(vec![Val::Int(1)]).into(),
+                Val::vec(vec![Val::Float(1.0.into())]),
+            ),
+        ];
+        for (a, b) in alike.iter() {
+            assert_eq!(a, b);
+            assert_eq!(a.cmp(b), Ordering::Equal);
+            assert_eq!(hash(a), hash(b), "{} and {}", a, b);
+        }
+
+        let big = Val::from(BigInt::from(1) << 70);
+        assert_eq!(big, Val::Float(2f64.powi(70).into()));
+        assert_eq!(hash(&big), hash(&Val::Float(2f64.powi(70).into())));
+        assert!(Val::Float(f64::INFINITY.into()) > Val::from(BigInt::from(10).pow(400)));
+    }
+
+    #[test]
+    fn metadata() {
+        let envt = Env::default();
+        assert_eq!(show(&envt, "(meta [1 2])"), "nil");
+        assert_eq!(show(&envt, "(meta (with-meta [1 2] {:a 1}))"), "{:a 1}");
+        assert_eq!(show(&envt, "(meta ^{:a 1} (list 1 2))"), "{:a 1}");
+        assert_eq!(show(&envt, "(= [1 2] (with-meta [1 2] {:a 1}))"), "true");
+
+        rep(&envt, "(def! f (fn* (x) x))").unwrap();
+        assert_eq!(show(&envt, "(meta f)"), "{:name f}");
+        assert_eq!(show(&envt, "(meta (with-meta f {:a 1}))"), "{:a 1}");
+        assert_eq!(show(&envt, "(meta +)"), "nil");
+        assert_eq!(show(&envt, "(meta (fn* (x) x))"), "nil");
+        assert_eq!(show(&envt, "((with-meta f {:a 1}) 7)"), "7");
+    }
+
+    #[test]
+    fn sequential_equality() {
+        let envt = Env::default();
+        assert_eq!(
+            show(
+                &envt,
+                "[(= '(1 2) [1 2]) (= [1 [2 3]] '(1 (2 3))) (= '() []) (= [1] [1 2])]"
+            ),
+            "[true true true false]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(= {:a [1]} {:a '(1)}) (= {:a 1} {:a 1 :b 2}) (= (atom 1) (atom 1))]"
+            ),
+            "[true false false]"
+        );
+        assert_eq!(
+            show(&envt, "(let* [f (fn* [] 1)] [(= f f) (= f (fn* [] 1))])"),
+            "[true false]"
+        );
+        rep(
+            &envt,
+            "(def! upto (fn* [n acc] (if (= n 0) acc (upto (- n 1) (cons n acc)))))",
+        )
+        .unwrap();
+        assert_eq!(show(&envt, "(= (upto 2000 ()) (upto 2000 []))"), "true");
+    }
 }
@@ -0,0 +1,161 @@
+/*!
+The ATOM type: a shared, mutable reference to a value.
+*/
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    env::Env,
+    types::{Lambda, List},
+    MalErr, Val,
+};
+
+#[derive(Debug)]
+pub struct Atom {
+    val: RwLock<Val>,
+    watches: RwLock<Vec<(Val, Arc<dyn Lambda>)>>,
+}
+
+impl Atom {
+    pub fn new(val: Val) -> Atom {
+        Atom {
+            val: RwLock::new(val),
+            watches: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn get(&self) -> Val {
+        self.val.read().unwrap().clone()
+    }
+
+    /// Sets the value to `new`, returning the old value.
+    pub fn reset(&self, new: Val) -> Val {
+        std::mem::replace(&mut *self.val.write().unwrap(), new)
+    }
+
+    /**
+    Sets the value to `new` only if it is still `old` itself (see
+    `Val::identical`), not merely equal to it: that takes constant time
+    however big the value, and `1.0` doesn't stand in for `1`.
+    */
+    pub fn compare_and_set(&self, old: &Val, new: Val) -> bool {
+        let mut val = self.val.write().unwrap();
+        if val.identical(old) {
+            *val = new;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds (or replaces) the watch function with the given `key`.
+    pub fn add_watch(&self, key: Val, func: Arc<dyn Lambda>) {
+        let mut watches = self.watches.write().unwrap();
+        match watches.iter_mut().find(|(k, _)| k == &key) {
+            Some(watch) => watch.1 = func,
+            None => watches.push((key, func)),
+        }
+    }
+
+    pub fn remove_watch(&self, key: &Val) {
+        self.watches.write().unwrap().retain(|(k, _)| k != key);
+    }
+
+    /// Calls each watch function as `(f key atom old new)`.
+    pub fn notify(self: &Arc<Atom>, envt: &Arc<Env>, old: Val, new: Val) -> Result<(), MalErr> {
+        // Clone the watches so they can add or remove watches themselves.
+        let watches = self.watches.read().unwrap().clone();
+        for (key, func) in watches.into_iter() {
+            let args = List::from_vec(vec![key, Val::Atom(self.clone()), old.clone(), new.clone()]);
+            func.call(envt, args)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+        Val,
+    };
+
+    #[test]
+    fn atoms() {
+        let envt = Env::default();
+        assert_eq!(show(&envt, "(def! a (atom 2))"), "(atom 2)");
+        assert_eq!(show(&envt, "(swap! a (fn* (x y) (* x y)) 10)"), "20");
+        assert_eq!(show(&envt, "@a"), "20");
+        assert_eq!(show(&envt, "(reset! a 3)"), "3");
+        assert_eq!(show(&envt, "(deref a)"), "3");
+        assert_eq!(show(&envt, "(= a a)"), "true");
+        assert_eq!(show(&envt, "(= a (atom 3))"), "false");
+    }
+
+    #[test]
+    fn compare_and_set_and_watches() {
+        let envt = Env::default();
+        rep(&envt, "(def! a (atom 1))").unwrap();
+        assert_eq!(show(&envt, "(compare-and-set! a 2 3)"), "false");
+        assert_eq!(show(&envt, "(compare-and-set! a 1 3)"), "true");
+        assert_eq!(show(&envt, "@a"), "3");
+        assert_eq!(show(&envt, "(compare-and-set! a 3.0 4)"), "false");
+        assert_eq!(show(&envt, "(compare-and-set! a [] 4)"), "false");
+        rep(&envt, "(def! v [1])").unwrap();
+        rep(&envt, "(reset! a v)").unwrap();
+        assert_eq!(show(&envt, "(compare-and-set! a [1] 4)"), "false");
+        assert_eq!(show(&envt, "(compare-and-set! a v 3)"), "true");
+
+        rep(&envt, "(def! seen (atom ()))").unwrap();
+        rep(
+            &envt,
+            "(add-watch a :log (fn* (k r old new) (reset! seen (list k old new))))",
+        )
+        .unwrap();
+        rep(&envt, "(add-watch a :builtin list)").unwrap();
+        rep(&envt, "(swap! a + 1)").unwrap();
+        assert_eq!(show(&envt, "@seen"), "(:log 3 4)");
+        rep(&envt, "(remove-watch a :log)").unwrap();
+        rep(&envt, "(reset! a 10)").unwrap();
+        assert_eq!(show(&envt, "@seen"), "(:log 3 4)");
+    }
+
+    #[test]
+    fn swap_under_contention() {
+        let envt = Env::default();
+        rep(&envt, "(def! n (atom 0))").unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let envt = envt.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        rep(&envt, "(swap! n (fn* (x) (+ x 1)))").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(rep(&envt, "@n").unwrap(), Val::Int(1000));
+
+        // The compare-and-set in swap! goes by identity, so it succeeds on
+        // functions and on values that contain them.
+        rep(&envt, "(def! e (atom {\"+\" +}))").unwrap();
+        rep(&envt, "(swap! e assoc \"-\" -)").unwrap();
+        assert_eq!(rep(&envt, "(count @e)").unwrap(), Val::Int(2));
+        assert_eq!(rep(&envt, "(= + +)").unwrap(), Val::True);
+
+        // Nor does it compare the whole value each time.
+        rep(&envt, "(def! m (atom {}))").unwrap();
+        rep(
+            &envt,
+            "(def! fill (fn* [i] (if (< i 20000) (do (swap! m assoc i i) (fill (+ i 1))))))",
+        )
+        .unwrap();
+        rep(&envt, "(fill 0)").unwrap();
+        assert_eq!(rep(&envt, "(count @m)").unwrap(), Val::Int(20000));
+    }
+}
@@ -1,63 +1,541 @@
 /*!
 Builtin functions.
 */
-use std::sync::Arc;
+use std::{
+    cmp::Ordering,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    error::{err, rerr},
-    types::{List, StaticFunc},
-    Res, Val,
+    env::Env,
+    error::{rerr, thrown},
+    eval::{eval, eval_forms},
+    printer::pr_join,
+    read::{read_file, read_str},
+    types::{Atom, BuiltinDef, EnvFunc, List, StaticFunc},
+    MalErr, Res, Val,
 };
 
 pub mod math;
+pub mod ns;
+pub mod numeric;
+pub mod seq;
+
+pub const BUILTINS: &[BuiltinDef<StaticFunc>] = &[
+    (
+        "=",
+        &equal,
+        "(= x & xs)",
+        "Whether the arguments are all equal values; numbers of different\nkinds are equal when their values are, as in (= 1 1.0). Unlike ==,\n##NaN is equal to itself, so it can be found as a map key.",
+    ),
+    (
+        "compare",
+        &compare,
+        "(compare a b)",
+        "-1, 0 or 1 as a comes before, with or after b: nil first, then\nnumbers by value, strings, symbols and keywords by text, and lists and\nvectors item by item.",
+    ),
+    (
+        "pr-str",
+        &pr_str,
+        "(pr-str & xs)",
+        "The readable printed forms of xs, separated by spaces.",
+    ),
+    (
+        "str",
+        &str_builtin,
+        "(str & xs)",
+        "The display forms of xs (strings without quotes), run together.",
+    ),
+    (
+        "prn",
+        &prn,
+        "(prn & xs)",
+        "Prints the readable forms of xs, separated by spaces, and a newline.",
+    ),
+    (
+        "println",
+        &println,
+        "(println & xs)",
+        "Prints the display forms of xs, separated by spaces, and a newline.",
+    ),
+    (
+        "nil?",
+        &nil_p,
+        "(nil? x)",
+        "Whether x is nil.",
+    ),
+    (
+        "true?",
+        &true_p,
+        "(true? x)",
+        "Whether x is true.",
+    ),
+    (
+        "false?",
+        &false_p,
+        "(false? x)",
+        "Whether x is false.",
+    ),
+    (
+        "number?",
+        &number_p,
+        "(number? x)",
+        "Whether x is a number.",
+    ),
+    (
+        "integer?",
+        &integer_p,
+        "(integer? x)",
+        "Whether x is an integer, of any size.",
+    ),
+    (
+        "ratio?",
+        &ratio_p,
+        "(ratio? x)",
+        "Whether x is an exact fraction that isn't an integer.",
+    ),
+    (
+        "float?",
+        &float_p,
+        "(float? x)",
+        "Whether x is a floating-point number.",
+    ),
+    (
+        "string?",
+        &string_p,
+        "(string? x)",
+        "Whether x is a string.",
+    ),
+    (
+        "symbol?",
+        &symbol_p,
+        "(symbol? x)",
+        "Whether x is a symbol.",
+    ),
+    (
+        "keyword?",
+        &keyword_p,
+        "(keyword? x)",
+        "Whether x is a keyword.",
+    ),
+    (
+        "fn?",
+        &fn_p,
+        "(fn? x)",
+        "Whether x is a function, not counting macros.",
+    ),
+    (
+        "macro?",
+        &macro_p,
+        "(macro? x)",
+        "Whether x is a macro.",
+    ),
+    (
+        "symbol",
+        &symbol,
+        "(symbol name)",
+        "The symbol named by the string name.",
+    ),
+    (
+        "keyword",
+        &keyword,
+        "(keyword name)",
+        "The keyword named by the string name; a keyword is returned as is.",
+    ),
+    (
+        "read-string",
+        &read_string,
+        "(read-string s)",
+        "The first form read from the string s.",
+    ),
+    (
+        "readline",
+        &readline,
+        "(readline prompt)",
+        "A line typed at the terminal after showing prompt, or nil at the end\nof the input.",
+    ),
+    (
+        "time-ms",
+        &time_ms,
+        "(time-ms)",
+        "The milliseconds since the Unix epoch.",
+    ),
+    (
+        "slurp",
+        &slurp,
+        "(slurp path)",
+        "The contents of the file at path, as a string.",
+    ),
+    (
+        "throw",
+        &throw,
+        "(throw x)",
+        "Throws x, for a surrounding try* to catch.",
+    ),
+    (
+        "atom",
+        &atom,
+        "(atom x)",
+        "A new atom holding x.",
+    ),
+    (
+        "atom?",
+        &atom_p,
+        "(atom? x)",
+        "Whether x is an atom.",
+    ),
+    (
+        "deref",
+        &deref,
+        "(deref a)",
+        "The value an atom holds; @a for short.",
+    ),
+    (
+        "add-watch",
+        &add_watch,
+        "(add-watch a key f)",
+        "Calls (f key a old new) whenever the atom a changes. Replaces any watch\nwith the same key.",
+    ),
+    (
+        "remove-watch",
+        &remove_watch,
+        "(remove-watch a key)",
+        "Removes the watch with the given key from the atom a.",
+    ),
+    (
+        "doc",
+        &doc,
+        "(doc f)",
+        "Prints how the function f is called and its docstring.",
+    ),
+    (
+        "meta",
+        &meta,
+        "(meta x)",
+        "The metadata attached to x, or nil.",
+    ),
+    (
+        "with-meta",
+        &with_meta,
+        "(with-meta x m)",
+        "A copy of the collection or function x with metadata m.",
+    ),
+];
 
-pub const BUILTINS: &[(&str, &StaticFunc)] = &[
-    ("list?", &list_p),
-    ("count", &count),
-    ("empty?", &is_empty),
-    ("list", &make_list),
-    ("=", &equal),
-    ("prn", &prn),
+/// Builtins that evaluate mal code, and so need an environment.
+pub const ENV_BUILTINS: &[BuiltinDef<EnvFunc>] = &[
+    (
+        "eval",
+        &eval_builtin,
+        "(eval form)\n(eval form env)",
+        "Evaluates form in the environment env, or in the current namespace.",
+    ),
+    (
+        "load-file",
+        &load_file,
+        "(load-file path)",
+        "Evaluates every form in the file at path.",
+    ),
+    (
+        "reset!",
+        &reset,
+        "(reset! a x)",
+        "Sets the atom a to hold x, and returns x.",
+    ),
+    (
+        "swap!",
+        &swap,
+        "(swap! a f & args)",
+        "Sets the atom a to (f old args...), retrying if another thread changes\nit meanwhile, and returns the new value.",
+    ),
+    (
+        "compare-and-set!",
+        &compare_and_set,
+        "(compare-and-set! a old new)",
+        "Sets the atom a to new only if it still holds old; returns whether it\ndid.",
+    ),
 ];
 
-pub fn list_p(args: Arc<List>) -> Res {
-    match args.car() {
-        Ok(Val::List(_)) => Ok(Val::True),
+pub fn equal(args: Arc<List>) -> Res {
+    let args = Val::from(args).unwrap_seq()?;
+    if args.is_empty() {
+        return rerr("= requires at least one argument");
+    }
+    Ok(args.windows(2).all(|p| p[0] == p[1]).into())
+}
+
+pub fn compare(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let (a, b) = match (args.next(), args.next()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return rerr("compare requires two arguments"),
+    };
+
+    let n: i64 = match a.compare(&b)? {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    };
+    Ok(n.into())
+}
+
+pub fn pr_str(args: Arc<List>) -> Res {
+    Ok(pr_join(Val::from(args).unwrap_seq()?, true, " ").into())
+}
+
+pub fn str_builtin(args: Arc<List>) -> Res {
+    Ok(pr_join(Val::from(args).unwrap_seq()?, false, "").into())
+}
+
+pub fn prn(args: Arc<List>) -> Res {
+    println!("{}", pr_join(Val::from(args).unwrap_seq()?, true, " "));
+    Ok(Val::Nil)
+}
+
+pub fn println(args: Arc<List>) -> Res {
+    println!("{}", pr_join(Val::from(args).unwrap_seq()?, false, " "));
+    Ok(Val::Nil)
+}
+
+pub fn nil_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Nil).into())
+}
+
+pub fn true_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::True).into())
+}
+
+pub fn false_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::False).into())
+}
+
+pub fn number_p(args: Arc<List>) -> Res {
+    Ok(args.car()?.is_number().into())
+}
+
+pub fn integer_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Int(_) | Val::BigInt(_)).into())
+}
+
+pub fn ratio_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Ratio(_)).into())
+}
+
+pub fn float_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Float(_)).into())
+}
+
+pub fn string_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::String(_)).into())
+}
+
+pub fn symbol_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Symbol(_)).into())
+}
+
+pub fn keyword_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Keyword(_)).into())
+}
+
+pub fn fn_p(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::Func(f, _) => Ok((!f.as_function().is_some_and(|f| f.is_macro())).into()),
         _ => Ok(Val::False),
     }
 }
 
-pub fn count(args: Arc<List>) -> Res {
-    match args.car() {
-        Ok(Val::List(list)) => Ok(list.len().into()),
-        Ok(Val::Nil) => Ok(0.into()),
-        _ => Err(err("count requires a countable argument")),
+pub fn macro_p(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::Func(f, _) => Ok(f.as_function().is_some_and(|f| f.is_macro()).into()),
+        _ => Ok(Val::False),
     }
 }
 
-pub fn is_empty(args: Arc<List>) -> Res {
-    Ok((count(args)? == Val::Int(0)).into())
+pub fn symbol(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::String(s) | Val::Symbol(s) => Ok(Val::Symbol(s)),
+        x => rerr(format!("cannot make a symbol from {}", &x)),
+    }
 }
 
-pub fn make_list(args: Arc<List>) -> Res {
-    Ok(args.into())
+pub fn keyword(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::String(s) | Val::Keyword(s) => Ok(Val::Keyword(s)),
+        x => rerr(format!("cannot make a keyword from {}", &x)),
+    }
 }
 
-pub fn equal(args: Arc<List>) -> Res {
+fn unwrap_string(v: Val) -> Result<Arc<str>, MalErr> {
+    match v {
+        Val::String(s) => Ok(s),
+        _ => rerr("expected a string"),
+    }
+}
+
+pub fn read_string(args: Arc<List>) -> Res {
+    read_str(&unwrap_string(args.car()?)?)
+}
+
+pub fn readline(args: Arc<List>) -> Res {
+    use rustyline::{error::ReadlineError, DefaultEditor};
+
+    let prompt = unwrap_string(args.car()?)?;
+    let line = DefaultEditor::new().and_then(|mut editor| editor.readline(&prompt));
+    match line {
+        Ok(line) => Ok(line.into()),
+        Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(Val::Nil),
+        Err(e) => rerr(format!("unable to read input: {}", &e)),
+    }
+}
+
+pub fn time_ms(_args: Arc<List>) -> Res {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    Ok(now.into())
+}
+
+pub fn slurp(args: Arc<List>) -> Res {
+    let path = unwrap_string(args.car()?)?;
+    match std::fs::read_to_string(path.as_ref()) {
+        Ok(text) => Ok(text.into()),
+        Err(e) => rerr(format!("unable to read {:?}: {}", &path, &e)),
+    }
+}
+
+pub fn throw(args: Arc<List>) -> Res {
+    Err(thrown(args.car()?))
+}
+
+pub fn eval_builtin(envt: &Arc<Env>, args: Arc<List>) -> Res {
     let mut args = args.clone();
-    let (a, b) = match (args.next(), args.next()) {
-        (Some(a), Some(b)) => (a, b),
-        _ => return rerr("= requires two arguments"),
-    };
+    let form = args.pop()?;
+    match args.next() {
+        Some(Val::Env(e)) => eval(&e, form),
+        Some(x) => rerr(format!("expected an environment, not {}", x)),
+        None => eval(&envt.current_ns(), form),
+    }
+}
 
-    Ok((a == b).into())
+pub fn load_file(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let path = unwrap_string(args.car()?)?;
+    let forms = read_file(&path)?;
+    envt.in_current_ns(|ns| eval_forms(ns, forms))?;
+    Ok(Val::Nil)
 }
 
-pub fn prn(args: Arc<List>) -> Res {
+pub fn atom(args: Arc<List>) -> Res {
+    Ok(Val::Atom(Arc::new(Atom::new(args.car()?))))
+}
+
+pub fn atom_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Atom(_)).into())
+}
+
+fn unwrap_atom(v: Val) -> Result<Arc<Atom>, MalErr> {
+    match v {
+        Val::Atom(a) => Ok(a),
+        _ => rerr("expected an atom"),
+    }
+}
+
+pub fn deref(args: Arc<List>) -> Res {
+    Ok(unwrap_atom(args.car()?)?.get())
+}
+
+pub fn reset(envt: &Arc<Env>, args: Arc<List>) -> Res {
     let mut args = args.clone();
-    while let Some(a) = args.next() {
-        print!(" {}", a);
+    let a = unwrap_atom(args.pop()?)?;
+    let new = args.pop()?;
+    let old = a.reset(new.clone());
+    a.notify(envt, old, new.clone())?;
+    Ok(new)
+}
+
+/**
+`(swap! a f args...)` sets `a` to `(f @a args...)`.
+
+`f` runs without any lock held (it may well deref `a`), so if another
+thread changes `a` in the meantime, `f` is retried with the new value.
+*/
+pub fn swap(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let a = unwrap_atom(args.pop()?)?;
+    let func = args.pop()?.unwrap_func()?;
+
+    loop {
+        let old = a.get();
+        let new = func.call(envt, args.cons(old.clone()))?;
+        if a.compare_and_set(&old, new.clone()) {
+            a.notify(envt, old, new.clone())?;
+            return Ok(new);
+        }
+    }
+}
+
+/// `(compare-and-set! a old new)` sets `a` to `new` if its value is `old`.
+pub fn compare_and_set(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let a = unwrap_atom(args.pop()?)?;
+    let old = args.pop()?;
+    let new = args.pop()?;
+
+    if a.compare_and_set(&old, new.clone()) {
+        a.notify(envt, old, new)?;
+        Ok(Val::True)
+    } else {
+        Ok(Val::False)
     }
-    println!();
+}
+
+/// `(add-watch a key f)` calls `(f key a old new)` after every change to `a`.
+pub fn add_watch(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let val = args.pop()?;
+    let a = unwrap_atom(val.clone())?;
+    let key = args.pop()?;
+    let func = args.pop()?.unwrap_func()?;
+    a.add_watch(key, func);
+    Ok(val)
+}
+
+pub fn remove_watch(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let val = args.pop()?;
+    unwrap_atom(val.clone())?.remove_watch(&args.pop()?);
+    Ok(val)
+}
+
+pub fn doc(args: Arc<List>) -> Res {
+    println!("{}", args.car()?.unwrap_func()?.describe());
     Ok(Val::Nil)
 }
+
+pub fn meta(args: Arc<List>) -> Res {
+    Ok(args.car()?.meta())
+}
+
+pub fn with_meta(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let val = args.pop()?;
+    val.with_meta(args.pop()?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{env::Env, eval::test::show};
+
+    #[test]
+    fn time_ms() {
+        let envt = Env::default();
+        // Some time after 2020, and never going backwards.
+        assert_eq!(
+            show(
+                &envt,
+                "(let* [t (time-ms)] [(> t 1577836800000) (<= t (time-ms))])"
+            ),
+            "[true true]"
+        );
+    }
+}
@@ -1,74 +1,150 @@
 /*!
 Built-in arithmetic functions.
+
+Numbers form a tower: integers (`Int`s, becoming `BigInt`s when they
+would overflow), then exact ratios, then floats. Arithmetic on two
+numbers happens at the level of the higher one, and `/` on integers
+gives a ratio unless they divide exactly.
+
+```text
+(* 4611686018427387904 2)  ; 9223372036854775808
+(/ 1 3)                    ; 1/3
+(+ 1/3 0.5)                ; 0.8333333333333333
+```
 */
 use std::sync::Arc;
 use std::{
     cmp::Ordering,
-    ops::{Add, Div, Mul, Sub},
+    ops::{Add, Mul, Sub},
 };
 
-use ordered_float::OrderedFloat;
+use num_bigint::BigInt;
+use num_rational::BigRational;
 
 use crate::{
     error::{err, rerr},
-    types::{List, StaticFunc},
-    Res, Val,
+    types::{
+        number::{compare, promote},
+        BuiltinDef, List, Num, StaticFunc,
+    },
+    MalErr, Res, Val,
 };
 
-pub const BUILTINS: &[(&str, &StaticFunc)] = &[
-    ("+", &add),
-    ("-", &sub),
-    ("*", &mul),
-    ("/", &div),
-    ("div", &int_div),
-    ("mod", &int_mod),
-    ("sqrt", &sqrt),
-    ("<", &less_than),
-    ("<=", &less_or_eq),
-    (">", &greater_than),
-    (">=", &greater_or_eq),
+pub const BUILTINS: &[BuiltinDef<StaticFunc>] = &[
+    ("+", &add, "(+ & xs)", "The sum of the numbers; 0 if there are none."),
+    (
+        "-",
+        &sub,
+        "(- x & xs)",
+        "x minus each of the rest in turn, or x negated if it's alone.",
+    ),
+    (
+        "*",
+        &mul,
+        "(* & xs)",
+        "The product of the numbers; 1 if there are none.",
+    ),
+    (
+        "/",
+        &div,
+        "(/ x & xs)",
+        "x divided by each of the rest in turn, or 1/x if it's alone; exact (a\nratio, if need be) unless a float is involved; only an exact 0 can't be\na divisor.",
+    ),
+    (
+        "div",
+        &int_div,
+        "(div a b)",
+        "a divided by b, rounded toward zero.",
+    ),
+    ("sqrt", &sqrt, "(sqrt x)", "The square root of x."),
+    (
+        "numerator",
+        &numerator,
+        "(numerator r)",
+        "The numerator of the ratio r in lowest terms; an integer is its own.",
+    ),
+    (
+        "denominator",
+        &denominator,
+        "(denominator r)",
+        "The denominator of the ratio r in lowest terms; 1 for an integer.",
+    ),
+    (
+        "==",
+        &num_equal,
+        "(== x & xs)",
+        "Whether the numbers are all equal, whatever their kinds: (== 1 1.0).\nAs in IEEE arithmetic, ##NaN isn't equal to anything, itself included;\n= says it's equal to itself.",
+    ),
+    (
+        "<",
+        &less_than,
+        "(< x & xs)",
+        "Whether each number is less than the next.",
+    ),
+    (
+        "<=",
+        &less_or_eq,
+        "(<= x & xs)",
+        "Whether each number is less than or equal to the next.",
+    ),
+    (
+        ">",
+        &greater_than,
+        "(> x & xs)",
+        "Whether each number is greater than the next.",
+    ),
+    (
+        ">=",
+        &greater_or_eq,
+        "(>= x & xs)",
+        "Whether each number is greater than or equal to the next.",
+    ),
 ];
 
-fn binop<F, G>(f: F, g: G, a: Val, b: Val) -> Res
+/// Applies the operator whose versions for each level are `f` (`None` if
+/// the result overflows an `i64`), `g`, `h` and `k` to `a` and `b`, at
+/// the level of the higher of them.
+pub fn binop<F, G, H, K>(f: F, g: G, h: H, k: K, a: Val, b: Val) -> Res
 where
-    F: Fn(i64, i64) -> i64,
-    G: Fn(f64, f64) -> f64,
+    F: Fn(i64, i64) -> Option<i64>,
+    G: Fn(BigInt, BigInt) -> BigInt,
+    H: Fn(BigRational, BigRational) -> BigRational,
+    K: Fn(f64, f64) -> f64,
 {
-    let v: Val = match (a, b) {
-        (Val::Int(n), Val::Int(m)) => f(n, m).into(),
-        (Val::Int(n), Val::Float(y)) => g(n as f64, y.into()).into(),
-        (Val::Float(x), Val::Int(m)) => g(x.into(), m as f64).into(),
-        (Val::Float(x), Val::Float(y)) => g(x.into(), y.into()).into(),
-        _ => return rerr("numeric arguments required"),
+    let v: Val = match promote(a, b)? {
+        (Num::Int(n), Num::Int(m)) => match f(n, m) {
+            Some(r) => r.into(),
+            None => g(n.into(), m.into()).into(),
+        },
+        (Num::Big(n), Num::Big(m)) => g(n, m).into(),
+        (Num::Ratio(r), Num::Ratio(s)) => h(r, s).into(),
+        (x, y) => k(x.to_f64(), y.to_f64()).into(),
     };
 
     Ok(v)
 }
 
-fn bincmp(args: &Arc<List>, ok: &[Ordering]) -> Res {
-    let mut args = args.clone();
-    let (a, b) = match (args.next(), args.next()) {
-        (Some(a), Some(b)) => (a, b),
-        _ => return rerr("requires two arguments"),
-    };
-
-    let (x, y): (f64, f64) = match (a.clone().try_into(), b.clone().try_into()) {
-        (Ok(x), Ok(y)) => (x, y),
-        _ => return rerr("incomparable values"),
-    };
-
-    let b = match x.partial_cmp(&y) {
-        Some(b) => b,
-        None => return Ok(false.into()),
+/// Whether each argument orders as one of `ok` against the next.
+fn chain(args: &Arc<List>, ok: &[Ordering]) -> Res {
+    let args = Val::from(args.clone()).unwrap_seq()?;
+    match args.first() {
+        Some(a) => Num::try_from(a.clone())?,
+        None => return rerr("requires at least one argument"),
     };
-    Ok(ok.contains(&b).into())
+    for pair in args.windows(2) {
+        match compare(pair[0].clone(), pair[1].clone())? {
+            Some(o) if ok.contains(&o) => {}
+            _ => return Ok(false.into()),
+        }
+    }
+    Ok(true.into())
 }
 
 pub fn add(args: Arc<List>) -> Res {
     let mut args = args.clone();
     let mut r: Val = 0.into();
     while let Some(v) = args.next() {
-        r = binop(Add::add, Add::add, r, v)?;
+        r = binop(i64::checked_add, Add::add, Add::add, Add::add, r, v)?;
     }
     Ok(r)
 }
@@ -77,7 +153,7 @@ pub fn mul(args: Arc<List>) -> Res {
     let mut args = args.clone();
     let mut r: Val = 1.into();
     while let Some(v) = args.next() {
-        r = binop(Mul::mul, Mul::mul, r, v)?;
+        r = binop(i64::checked_mul, Mul::mul, Mul::mul, Mul::mul, r, v)?;
     }
     Ok(r)
 }
@@ -88,69 +164,82 @@ pub fn sub(args: Arc<List>) -> Res {
     let mut r: Val = args
         .next()
         .ok_or_else(|| err("requires at least one argument"))?;
+    if args.is_empty() {
+        // Negate by multiplying, which keeps the sign of -0.0.
+        return binop(
+            i64::checked_mul,
+            Mul::mul,
+            Mul::mul,
+            Mul::mul,
+            (-1).into(),
+            r,
+        );
+    }
 
     while let Some(v) = args.next() {
-        r = binop(Sub::sub, Sub::sub, r, v)?;
+        r = binop(i64::checked_sub, Sub::sub, Sub::sub, Sub::sub, r, v)?;
     }
 
     Ok(r)
 }
 
+/// Exact division, giving a ratio, unless either argument is a float;
+/// then dividing by zero gives `##Inf`, `##-Inf` or `##NaN`.
+fn divide(num: Val, den: Val) -> Res {
+    let v: Val = match promote(num, den)? {
+        (Num::Float(x), Num::Float(y)) => (x / y).into(),
+        (_, y) if y.is_zero() => return rerr("division by zero"),
+        (x, y) => match (x.into_level(2), y.into_level(2)) {
+            (Num::Ratio(r), Num::Ratio(s)) => (r / s).into(),
+            _ => unreachable!("integers and ratios promote to ratios"),
+        },
+    };
+
+    Ok(v)
+}
+
 pub fn div(args: Arc<List>) -> Res {
     let mut args = args.clone();
-    let num = args
+    let mut r: Val = args
         .next()
         .ok_or_else(|| err("requires at least one argument"))?;
-    let den = match args.next() {
-        None => return Ok(num),
-        Some(v) => v,
-    };
+    if args.is_empty() {
+        return divide(1.into(), r);
+    }
 
-    let v: Val = match (num, den) {
-        (_, Val::Int(0)) => return rerr("division by zero"),
-        (_, Val::Float(OrderedFloat(x))) if x == 0.0 => {
-            return rerr("division by zero");
-        }
-        (Val::Int(n), Val::Int(m)) if n % m == 0 => (n / m).into(),
-        (Val::Int(n), Val::Int(m)) => (n as f64 / m as f64).into(),
-        (x, y) => binop(Div::div, Div::div, x, y)?,
-    };
+    while let Some(v) = args.next() {
+        r = divide(r, v)?;
+    }
 
-    Ok(v)
+    Ok(r)
 }
 
-pub fn int_div(args: Arc<List>) -> Res {
-    let mut args = args.clone();
-    let dividend = args.next().ok_or_else(|| err("requires two arguments"))?;
-    let divisor = args.next().ok_or_else(|| err("requires two arguments"))?;
-
-    match (dividend, divisor) {
-        (_, Val::Int(0)) => rerr("division by zero"),
-        (Val::Int(n), Val::Int(m)) => Ok((n / m).into()),
+/// The integers `a` and `b`, at the level of the higher of them.
+fn integers(a: Val, b: Val) -> Result<(Num, Num), MalErr> {
+    match promote(a, b)? {
+        (_, y) if y.is_zero() => rerr("division by zero"),
+        (x @ (Num::Int(_) | Num::Big(_)), y) => Ok((x, y)),
         _ => rerr("requires integer arguments"),
     }
 }
 
-pub fn int_mod(args: Arc<List>) -> Res {
+pub fn int_div(args: Arc<List>) -> Res {
     let mut args = args.clone();
     let dividend = args.next().ok_or_else(|| err("requires two arguments"))?;
     let divisor = args.next().ok_or_else(|| err("requires two arguments"))?;
 
-    match (dividend, divisor) {
-        (_, Val::Int(0)) => rerr("division by zero"),
-        (Val::Int(n), Val::Int(m)) => Ok((n % m).into()),
-        _ => rerr("requires integer arguments"),
+    match integers(dividend, divisor)? {
+        (Num::Int(n), Num::Int(m)) => match n.checked_div(m) {
+            Some(q) => Ok(q.into()),
+            None => Ok((BigInt::from(n) / m).into()),
+        },
+        (Num::Big(n), Num::Big(m)) => Ok((n / m).into()),
+        _ => unreachable!("integers promote to integers"),
     }
 }
 
 pub fn sqrt(args: Arc<List>) -> Res {
-    let arg = args.car()?;
-
-    let arg: f64 = match arg {
-        Val::Int(n) => n as f64,
-        Val::Float(x) => x.into(),
-        _ => return rerr("requires numeric argument"),
-    };
+    let arg = Num::try_from(args.car()?)?.to_f64();
 
     if arg < 0.0 {
         return rerr("requres non-negative argument");
@@ -159,15 +248,191 @@ pub fn sqrt(args: Arc<List>) -> Res {
     Ok(arg.sqrt().into())
 }
 
+pub fn numerator(args: Arc<List>) -> Res {
+    match args.car()? {
+        n @ (Val::Int(_) | Val::BigInt(_)) => Ok(n),
+        Val::Ratio(r) => Ok(r.numer().clone().into()),
+        x => rerr(format!(
+            "numerator requires an integer or ratio, not {}",
+            &x
+        )),
+    }
+}
+
+pub fn denominator(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::Int(_) | Val::BigInt(_) => Ok(1.into()),
+        Val::Ratio(r) => Ok(r.denom().clone().into()),
+        x => rerr(format!(
+            "denominator requires an integer or ratio, not {}",
+            &x
+        )),
+    }
+}
+
+pub fn num_equal(args: Arc<List>) -> Res {
+    chain(&args, &[Ordering::Equal])
+}
 pub fn less_than(args: Arc<List>) -> Res {
-    bincmp(&args, &[Ordering::Less])
+    chain(&args, &[Ordering::Less])
 }
 pub fn less_or_eq(args: Arc<List>) -> Res {
-    bincmp(&args, &[Ordering::Less, Ordering::Equal])
+    chain(&args, &[Ordering::Less, Ordering::Equal])
 }
 pub fn greater_than(args: Arc<List>) -> Res {
-    bincmp(&args, &[Ordering::Greater])
+    chain(&args, &[Ordering::Greater])
 }
 pub fn greater_or_eq(args: Arc<List>) -> Res {
-    bincmp(&args, &[Ordering::Greater, Ordering::Equal])
+    chain(&args, &[Ordering::Greater, Ordering::Equal])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
+
+    #[test]
+    fn numeric_tower() {
+        let envt = Env::default();
+        assert_eq!(
+            show(&envt, "(+ 9223372036854775807 1)"),
+            "9223372036854775808"
+        );
+        assert_eq!(
+            show(&envt, "(* 4294967296 4294967296 4294967296)"),
+            "79228162514264337593543950336"
+        );
+        assert_eq!(
+            show(&envt, "(- -9223372036854775808 1)"),
+            "-9223372036854775809"
+        );
+        assert_eq!(
+            show(&envt, "(- (+ 9223372036854775807 1) 1)"),
+            "9223372036854775807"
+        );
+        assert_eq!(
+            show(&envt, "(div -9223372036854775808 -1)"),
+            "9223372036854775808"
+        );
+        assert_eq!(show(&envt, "(mod 100000000000000000000 7)"), "2");
+
+        assert_eq!(show(&envt, "(/ 6 3)"), "2");
+        assert_eq!(show(&envt, "(/ 1 3)"), "1/3");
+        assert_eq!(show(&envt, "(/ 4 -6)"), "-2/3");
+        assert_eq!(show(&envt, "(+ 1/3 2/3)"), "1");
+        assert_eq!(show(&envt, "(* 1/2 0.5)"), "0.25");
+        assert_eq!(show(&envt, "(/ 1 2.0)"), "0.5");
+        assert_eq!(show(&envt, "(< 1/3 0.34)"), "true");
+        assert_eq!(show(&envt, "(<= 1/2 0.5)"), "true");
+        assert_eq!(
+            show(&envt, "(> 9223372036854775808 9223372036854775807)"),
+            "true"
+        );
+        assert_eq!(show(&envt, "(= 2/4 1/2)"), "true");
+        assert_eq!(
+            show(&envt, "(read-string \"12345678901234567890/2\")"),
+            "6172839450617283945"
+        );
+        assert!(rep(&envt, "(/ 1 0)").is_err());
+        assert_eq!(
+            show(&envt, "[(/ 1.0 0) (/ -1 0.0) (/ 0 0.0) (/ 0.0)]"),
+            "[##Inf ##-Inf ##NaN ##Inf]"
+        );
+        assert_eq!(
+            show(&envt, "[(/ 12 2 3) (/ 1 2 3) (/ 5) (/ 0.5) (/ 1/3)]"),
+            "[2 1/6 1/5 2.0 3]"
+        );
+        assert_eq!(
+            show(&envt, "[(- 5) (- 1/2) (- 0.0) (- -9223372036854775808)]"),
+            "[-5 -1/2 -0.0 9223372036854775808]"
+        );
+        assert_eq!(show(&envt, "(- 10 1 2)"), "7");
+        assert!(rep(&envt, "(/ 0)").is_err());
+        assert!(rep(&envt, "(/)").is_err());
+        assert!(rep(&envt, "1/0").is_err());
+
+        assert_eq!(
+            show(&envt, "[(numerator 6/4) (denominator 6/4) (denominator 5)]"),
+            "[3 2 1]"
+        );
+        let preds = "(map (fn* [x] [(integer? x) (ratio? x) (float? x) (number? x)]) xs)";
+        rep(&envt, "(def! xs [1 100000000000000000000 1/2 0.5 :a])").unwrap();
+        assert_eq!(
+            show(&envt, preds),
+            "([true false false true] [true false false true] [false true false true] \
+             [false false true true] [false false false false])"
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let envt = Env::default();
+        assert_eq!(
+            show(
+                &envt,
+                "[(< 1 2 3) (< 1 3 2) (<= 1 1 2) (> 3 2.5 1/2) (>= 2 2 3) (< 1)]"
+            ),
+            "[true false true true false true]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(< 9007199254740992 9007199254740993) (< 9007199254740993 9007199254740992.0)]"
+            ),
+            "[true false]"
+        );
+        assert!(rep(&envt, "(<)").is_err());
+        assert!(rep(&envt, "(< 1 \"a\")").is_err());
+
+        assert_eq!(
+            show(
+                &envt,
+                "[(= 1 1.0 2/2) (= 1/2 0.5) (= 9007199254740993 9007199254740992.0) (= :a :a :b)]"
+            ),
+            "[true true false false]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(== 1 1.0 2/2) (== 1 2) (== (pow 2 64) 18446744073709551616.0)]"
+            ),
+            "[true false true]"
+        );
+        assert!(rep(&envt, "(== :a :a)").is_err());
+
+        assert_eq!(
+            show(
+                &envt,
+                "[(compare 1 2) (compare 2.0 2) (compare 3 5/2) (compare nil 0)]"
+            ),
+            "[-1 0 1 -1]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(compare \"a\" \"b\") (compare :b :a) (compare false true)]"
+            ),
+            "[-1 1 -1]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(compare [1 2] [1 3]) (compare '(1 2) [1]) (compare [] '())]"
+            ),
+            "[-1 1 0]"
+        );
+        assert!(rep(&envt, "(compare 1 \"a\")").is_err());
+        assert!(rep(&envt, "(compare {} {})").is_err());
+
+        // == is IEEE equality; = is value equality, which maps rely on.
+        assert_eq!(
+            show(
+                &envt,
+                "[(== ##NaN ##NaN) (= ##NaN ##NaN) (get {##NaN 1} ##NaN)]"
+            ),
+            "[false true 1]"
+        );
+    }
 }
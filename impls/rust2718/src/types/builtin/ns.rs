@@ -0,0 +1,185 @@
+/*!
+Built-in functions on namespaces, which are named by symbols, and on
+environments, which are values: `*ENV*` is the one being evaluated in.
+
+```text
+(require '[str :as s] '[seqs :refer [take drop]])
+(ns-publics 's)  ; {join <fn>, split <fn>}
+(let* [e (env-new)] (env-set! e 'x 1) (eval '(+ x 1) e))  ; 2
+```
+*/
+use std::sync::Arc;
+
+use crate::{
+    env::Env,
+    error::{err, rerr},
+    types::{BuiltinDef, EnvFunc, List, Map},
+    MalErr, Res, Val,
+};
+
+pub const ENV_BUILTINS: &[BuiltinDef<EnvFunc>] = &[
+    (
+        "require",
+        &require,
+        "(require & specs)",
+        "Makes namespaces available to the current one, loading them from\nMAL_PATH if need be. Each spec is a namespace name, or a vector of the\nname followed by :as alias, :refer [names] or :refer :all.",
+    ),
+    (
+        "refer",
+        &refer,
+        "(refer ns)\n(refer ns names)",
+        "Makes the names (or all the public names) of the namespace ns\nusable unqualified in the current one.",
+    ),
+    (
+        "alias",
+        &alias,
+        "(alias a ns)",
+        "Lets the current namespace call the namespace ns by the name a.",
+    ),
+    (
+        "ns-publics",
+        &ns_publics,
+        "(ns-publics ns)",
+        "A map of the names the namespace ns defines to their values.",
+    ),
+    (
+        "all-ns",
+        &all_ns,
+        "(all-ns)",
+        "A list of the names of every namespace.",
+    ),
+    (
+        "env-new",
+        &env_new,
+        "(env-new)\n(env-new outer)",
+        "A new, empty environment inside outer, or inside the current\nnamespace; *outer* is bound to outer in it.",
+    ),
+    (
+        "env-get",
+        &env_get,
+        "(env-get env sym)",
+        "The value of the symbol sym in the environment env or the ones it's\ninside.",
+    ),
+    (
+        "env-set!",
+        &env_set,
+        "(env-set! env sym x)",
+        "Binds the symbol sym to x in the environment env, and returns x.",
+    ),
+];
+
+fn unwrap_env(v: Val) -> Result<Arc<Env>, MalErr> {
+    match v {
+        Val::Env(e) => Ok(e),
+        x => rerr(format!("expected an environment, not {}", x)),
+    }
+}
+
+/// The namespace `name` refers to from `envt`.
+fn find_ns(envt: &Arc<Env>, name: &str) -> Result<Arc<Env>, MalErr> {
+    envt.find_ns(name)
+        .ok_or_else(|| err(format!("no namespace '{}'", name)))
+}
+
+fn symbols(v: &Val) -> Result<Vec<Arc<str>>, MalErr> {
+    v.unwrap_seq()?.iter().map(Val::unwrap_symbol).collect()
+}
+
+/**
+Requires one namespace into the namespace of `envt`, loading it if need
+be: `spec` is its name, or a vector of the name and options, as in
+`[str :as s :refer [join]]`.
+*/
+pub fn require_spec(envt: &Arc<Env>, spec: &Val) -> Result<(), MalErr> {
+    let (name, opts) = match spec {
+        Val::Symbol(name) => (name.clone(), Vec::new()),
+        Val::List(..) | Val::Vector(..) => match spec.unwrap_seq()?.split_first() {
+            Some((name, opts)) => (name.unwrap_symbol()?, opts.to_vec()),
+            None => return rerr("require needs a namespace name"),
+        },
+        x => return rerr(format!("invalid require spec: {}", x)),
+    };
+    let target = envt.require_ns(&name)?;
+
+    for opt in opts.chunks(2) {
+        match opt {
+            [Val::Keyword(k), Val::Symbol(a)] if k.as_ref() == "as" => {
+                envt.add_alias(a, &target);
+            }
+            [Val::Keyword(k), Val::Keyword(all)]
+                if k.as_ref() == "refer" && all.as_ref() == "all" =>
+            {
+                envt.refer(&target, None)?;
+            }
+            [Val::Keyword(k), names] if k.as_ref() == "refer" => {
+                envt.refer(&target, Some(symbols(names)?))?;
+            }
+            _ => return rerr(format!("invalid require spec: {}", spec)),
+        }
+    }
+    Ok(())
+}
+
+pub fn require(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    for spec in Val::from(args).unwrap_seq()? {
+        require_spec(envt, &spec)?;
+    }
+    Ok(Val::Nil)
+}
+
+pub fn refer(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let from = find_ns(envt, &args.pop()?.unwrap_symbol()?)?;
+    let names = match args.next() {
+        Some(names) => Some(symbols(&names)?),
+        None => None,
+    };
+    envt.refer(&from, names)?;
+    Ok(Val::Nil)
+}
+
+pub fn alias(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let alias = args.pop()?.unwrap_symbol()?;
+    let target = find_ns(envt, &args.pop()?.unwrap_symbol()?)?;
+    envt.add_alias(&alias, &target);
+    Ok(Val::Nil)
+}
+
+pub fn ns_publics(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let ns = find_ns(envt, &args.car()?.unwrap_symbol()?)?;
+    let mut m = Map::default();
+    for (name, val) in ns.publics() {
+        m = m.assoc(Val::Symbol(name), val);
+    }
+    Ok(m.into())
+}
+
+pub fn all_ns(envt: &Arc<Env>, _args: Arc<List>) -> Res {
+    let names = envt.all_ns().into_iter().map(Val::Symbol).collect();
+    Ok(List::from_vec(names).into())
+}
+
+pub fn env_new(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let outer = match args.car() {
+        Ok(v) => unwrap_env(v)?,
+        Err(_) => envt.current_ns(),
+    };
+    let new_envt = Env::binding(&outer, vec![("*outer*".into(), Val::Env(outer.clone()))]);
+    Ok(Val::Env(new_envt))
+}
+
+pub fn env_get(_envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let envt = unwrap_env(args.pop()?)?;
+    envt.get(args.pop()?.unwrap_symbol()?)
+}
+
+pub fn env_set(_envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let envt = unwrap_env(args.pop()?)?;
+    let key = args.pop()?.unwrap_symbol()?;
+    let val = args.pop()?;
+    envt.set(&key, val.clone());
+    Ok(val)
+}
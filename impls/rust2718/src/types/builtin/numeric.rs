@@ -0,0 +1,552 @@
+/*!
+More numeric functions: rounding, powers and logarithms, trigonometry,
+integer division, bitwise operations and random numbers.
+
+Each works at the level of the tower its arguments need (see `math`):
+exact where it can be, and floating-point otherwise.
+
+```text
+(mod -7 2)          ; 1
+(rem -7 2)          ; -1
+(pow 2/3 2)         ; 4/9
+(bit-shift-left 1 70)  ; 1180591620717411303424
+```
+*/
+use std::{
+    cmp::Ordering,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, Zero};
+use once_cell::sync::Lazy;
+
+use super::math::binop;
+use crate::{
+    error::{err, rerr},
+    types::{
+        number::{compare, promote},
+        BuiltinDef, List, Num, StaticFunc,
+    },
+    MalErr, Res, Val,
+};
+
+/// The most bits `pow` and `bit-shift-left` give an exact result (about
+/// five million decimal digits), so a typo can't hang or exhaust memory.
+const MAX_BITS: u64 = 1 << 24;
+
+pub const BUILTINS: &[BuiltinDef<StaticFunc>] = &[
+    ("abs", &abs, "(abs x)", "The absolute value of x."),
+    (
+        "min",
+        &min,
+        "(min x & xs)",
+        "The least of the numbers given.",
+    ),
+    (
+        "max",
+        &max,
+        "(max x & xs)",
+        "The greatest of the numbers given.",
+    ),
+    (
+        "floor",
+        &floor,
+        "(floor x)",
+        "The greatest integer not greater than x.",
+    ),
+    (
+        "ceil",
+        &ceil,
+        "(ceil x)",
+        "The least integer not less than x.",
+    ),
+    (
+        "round",
+        &round,
+        "(round x)",
+        "The integer nearest to x, rounding halves away from zero.",
+    ),
+    (
+        "pow",
+        &pow,
+        "(pow x n)",
+        "x to the power n; exact if x is exact and n is an integer.",
+    ),
+    ("exp", &exp, "(exp x)", "e to the power x."),
+    ("log", &log, "(log x)", "The natural logarithm of x."),
+    ("sin", &sin, "(sin x)", "The sine of x radians."),
+    ("cos", &cos, "(cos x)", "The cosine of x radians."),
+    ("tan", &tan, "(tan x)", "The tangent of x radians."),
+    (
+        "atan2",
+        &atan2,
+        "(atan2 y x)",
+        "The angle in radians from the positive x axis to the point (x, y).",
+    ),
+    (
+        "quot",
+        &quot,
+        "(quot a b)",
+        "a divided by b, rounded toward zero.",
+    ),
+    (
+        "rem",
+        &rem,
+        "(rem a b)",
+        "The remainder of (quot a b), which has the sign of a.",
+    ),
+    (
+        "mod",
+        &modulo,
+        "(mod a b)",
+        "a modulo b, which has the sign of b.",
+    ),
+    (
+        "bit-and",
+        &bit_and,
+        "(bit-and a b)",
+        "The bitwise and of the integers a and b.",
+    ),
+    (
+        "bit-or",
+        &bit_or,
+        "(bit-or a b)",
+        "The bitwise or of the integers a and b.",
+    ),
+    (
+        "bit-xor",
+        &bit_xor,
+        "(bit-xor a b)",
+        "The bitwise exclusive or of the integers a and b.",
+    ),
+    (
+        "bit-shift-left",
+        &bit_shift_left,
+        "(bit-shift-left a n)",
+        "The integer a shifted left by n bits; it never overflows.",
+    ),
+    (
+        "bit-shift-right",
+        &bit_shift_right,
+        "(bit-shift-right a n)",
+        "The integer a shifted right by n bits, keeping its sign.",
+    ),
+    (
+        "rand",
+        &rand,
+        "(rand)\n(rand n)",
+        "A random float at least 0 and less than n (or 1).",
+    ),
+    (
+        "rand-int",
+        &rand_int,
+        "(rand-int n)",
+        "A random integer at least 0 and less than the positive integer n.",
+    ),
+    (
+        "rand-seed!",
+        &rand_seed,
+        "(rand-seed! n)",
+        "Restarts the random numbers from the integer seed n, so the same\nseed gives the same numbers.",
+    ),
+];
+
+/// The one argument of a function of a number.
+fn arg(args: &Arc<List>) -> Result<Num, MalErr> {
+    Num::try_from(args.car()?)
+}
+
+/// The two arguments of a function of two numbers.
+fn args2(args: &Arc<List>) -> Result<(Val, Val), MalErr> {
+    let mut args = args.clone();
+    Ok((args.pop()?, args.pop()?))
+}
+
+pub fn abs(args: Arc<List>) -> Res {
+    let v: Val = match arg(&args)? {
+        Num::Int(n) => match n.checked_abs() {
+            Some(n) => n.into(),
+            None => BigInt::from(n).abs().into(),
+        },
+        Num::Big(n) => n.abs().into(),
+        Num::Ratio(r) => r.abs().into(),
+        Num::Float(x) => x.abs().into(),
+    };
+    Ok(v)
+}
+
+/// The argument that `compare`s as `keep` to all the others.
+fn extreme(args: Arc<List>, keep: Ordering) -> Res {
+    let mut args = args.clone();
+    let mut best = args.pop()?;
+    Num::try_from(best.clone())?;
+    while let Some(v) = args.next() {
+        if compare(v.clone(), best.clone())? == Some(keep) {
+            best = v;
+        }
+    }
+    Ok(best)
+}
+
+pub fn min(args: Arc<List>) -> Res {
+    extreme(args, Ordering::Less)
+}
+
+pub fn max(args: Arc<List>) -> Res {
+    extreme(args, Ordering::Greater)
+}
+
+/// Rounds `x` to an integer with `f` for ratios and `g` for floats.
+fn to_integer<F, G>(x: Num, f: F, g: G) -> Res
+where
+    F: Fn(&BigRational) -> BigRational,
+    G: Fn(f64) -> f64,
+{
+    match x {
+        Num::Int(_) | Num::Big(_) => Ok(x.into()),
+        Num::Ratio(r) => Ok(f(&r).to_integer().into()),
+        Num::Float(x) => match BigInt::from_f64(g(x)) {
+            Some(n) => Ok(n.into()),
+            None => rerr(format!("{} has no nearest integer", x)),
+        },
+    }
+}
+
+pub fn floor(args: Arc<List>) -> Res {
+    to_integer(arg(&args)?, BigRational::floor, f64::floor)
+}
+
+pub fn ceil(args: Arc<List>) -> Res {
+    to_integer(arg(&args)?, BigRational::ceil, f64::ceil)
+}
+
+pub fn round(args: Arc<List>) -> Res {
+    to_integer(arg(&args)?, BigRational::round, f64::round)
+}
+
+pub fn pow(args: Arc<List>) -> Res {
+    let (x, n) = args2(&args)?;
+    match (Num::try_from(x)?, Num::try_from(n)?) {
+        (x @ (Num::Int(_) | Num::Big(_) | Num::Ratio(_)), Num::Int(n)) => {
+            let n = i32::try_from(n).map_err(|_| err("pow requires a smaller exponent"))?;
+            match x.into_level(2) {
+                Num::Ratio(r) if r.is_zero() && n < 0 => rerr("division by zero"),
+                Num::Ratio(r) => {
+                    // Roughly log2 of the larger of r's parts, times n.
+                    let bits = r.numer().bits().max(r.denom().bits()) - 1;
+                    if bits.saturating_mul(n.unsigned_abs().into()) > MAX_BITS {
+                        return rerr("pow's result would be too big");
+                    }
+                    Ok(r.pow(n).into())
+                }
+                _ => unreachable!("exact numbers promote to ratios"),
+            }
+        }
+        (x, n) => Ok(x.to_f64().powf(n.to_f64()).into()),
+    }
+}
+
+pub fn exp(args: Arc<List>) -> Res {
+    Ok(arg(&args)?.to_f64().exp().into())
+}
+
+pub fn log(args: Arc<List>) -> Res {
+    Ok(arg(&args)?.to_f64().ln().into())
+}
+
+pub fn sin(args: Arc<List>) -> Res {
+    Ok(arg(&args)?.to_f64().sin().into())
+}
+
+pub fn cos(args: Arc<List>) -> Res {
+    Ok(arg(&args)?.to_f64().cos().into())
+}
+
+pub fn tan(args: Arc<List>) -> Res {
+    Ok(arg(&args)?.to_f64().tan().into())
+}
+
+pub fn atan2(args: Arc<List>) -> Res {
+    let (y, x) = args2(&args)?;
+    let (y, x) = (Num::try_from(y)?.to_f64(), Num::try_from(x)?.to_f64());
+    Ok(y.atan2(x).into())
+}
+
+/// The two arguments of an integer division, failing if the divisor is
+/// zero.
+fn division(args: &Arc<List>) -> Result<(Val, Val), MalErr> {
+    let (a, b) = args2(args)?;
+    if Num::try_from(b.clone())?.is_zero() {
+        return rerr("division by zero");
+    }
+    Ok((a, b))
+}
+
+pub fn quot(args: Arc<List>) -> Res {
+    let (a, b) = division(&args)?;
+    binop(
+        i64::checked_div,
+        |n, m| n / m,
+        |r, s| (r / s).trunc(),
+        |x, y| (x / y).trunc(),
+        a,
+        b,
+    )
+}
+
+pub fn rem(args: Arc<List>) -> Res {
+    let (a, b) = division(&args)?;
+    binop(
+        // Only i64::MIN % -1 overflows, and that's 0.
+        |n, m| Some(n.checked_rem(m).unwrap_or(0)),
+        |n, m| n % m,
+        |r, s| {
+            let q = (&r / &s).trunc();
+            r - s * q
+        },
+        |x, y| x % y,
+        a,
+        b,
+    )
+}
+
+pub fn modulo(args: Arc<List>) -> Res {
+    let (a, b) = division(&args)?;
+    binop(
+        |n, m| {
+            let r = n.checked_rem(m).unwrap_or(0);
+            // r and m differ in sign, so this can't overflow.
+            Some(if r != 0 && (r < 0) != (m < 0) {
+                r + m
+            } else {
+                r
+            })
+        },
+        |n, m| n.mod_floor(&m),
+        |r, s| {
+            let q = (&r / &s).floor();
+            r - s * q
+        },
+        |x, y| {
+            let r = x % y;
+            if r != 0.0 && (r < 0.0) != (y < 0.0) {
+                r + y
+            } else {
+                r
+            }
+        },
+        a,
+        b,
+    )
+}
+
+/// The integers `a` and `b` as `BigInt`s.
+fn big_integers(a: Val, b: Val) -> Result<(BigInt, BigInt), MalErr> {
+    match promote(a, b)? {
+        (Num::Int(n), Num::Int(m)) => Ok((n.into(), m.into())),
+        (Num::Big(n), Num::Big(m)) => Ok((n, m)),
+        _ => rerr("requires integer arguments"),
+    }
+}
+
+/// Applies the bitwise operator `f`, on two's complement integers, to
+/// the arguments.
+fn bitop<F>(args: &Arc<List>, f: F) -> Res
+where
+    F: Fn(BigInt, BigInt) -> BigInt,
+{
+    let (a, b) = args2(args)?;
+    let (n, m) = big_integers(a, b)?;
+    Ok(f(n, m).into())
+}
+
+pub fn bit_and(args: Arc<List>) -> Res {
+    bitop(&args, |n, m| n & m)
+}
+
+pub fn bit_or(args: Arc<List>) -> Res {
+    bitop(&args, |n, m| n | m)
+}
+
+pub fn bit_xor(args: Arc<List>) -> Res {
+    bitop(&args, |n, m| n ^ m)
+}
+
+/// The integer to shift and the number of bits to shift it by.
+fn shift(args: &Arc<List>) -> Result<(BigInt, usize), MalErr> {
+    let (a, n) = args2(args)?;
+    let n = match n {
+        Val::Int(n) => usize::try_from(n).map_err(|_| err("requires a non-negative shift"))?,
+        x => return rerr(format!("requires an integer shift, not {}", &x)),
+    };
+    let (a, _) = big_integers(a, Val::Int(0))?;
+    Ok((a, n))
+}
+
+pub fn bit_shift_left(args: Arc<List>) -> Res {
+    let (a, n) = shift(&args)?;
+    if !a.is_zero() && a.bits().saturating_add(n as u64) > MAX_BITS {
+        return rerr("bit-shift-left's result would be too big");
+    }
+    Ok((a << n).into())
+}
+
+pub fn bit_shift_right(args: Arc<List>) -> Res {
+    let (a, n) = shift(&args)?;
+    Ok((a >> n).into())
+}
+
+/// The state of the random number generator: splitmix64, seeded from the
+/// clock unless `rand-seed!` says otherwise.
+static RANDOM: Lazy<Mutex<u64>> = Lazy::new(|| {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    Mutex::new(now)
+});
+
+/// The next 64 random bits.
+fn next_random() -> u64 {
+    let mut state = RANDOM.lock().unwrap();
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+pub fn rand(args: Arc<List>) -> Res {
+    let scale = match args.car() {
+        Ok(n) => Num::try_from(n)?.to_f64(),
+        Err(_) => 1.0,
+    };
+    // The top 53 bits, as a fraction of 2^53.
+    let x = (next_random() >> 11) as f64 / (1u64 << 53) as f64;
+    Ok((x * scale).into())
+}
+
+pub fn rand_int(args: Arc<List>) -> Res {
+    let n = match args.car()? {
+        Val::Int(n) if n > 0 => n as u64,
+        x => return rerr(format!("rand-int requires a positive integer, not {}", &x)),
+    };
+    // The high half of the product is below n, and nearly uniform.
+    let r = ((next_random() as u128 * n as u128) >> 64) as i64;
+    Ok(r.into())
+}
+
+pub fn rand_seed(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::Int(n) => *RANDOM.lock().unwrap() = n as u64,
+        x => return rerr(format!("rand-seed! requires an integer, not {}", &x)),
+    }
+    Ok(Val::Nil)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
+
+    #[test]
+    fn more_math() {
+        let envt = Env::default();
+        assert_eq!(
+            show(
+                &envt,
+                "[(abs -3) (abs -1/2) (abs -2.5) (abs -9223372036854775808)]"
+            ),
+            "[3 1/2 2.5 9223372036854775808]"
+        );
+        assert_eq!(
+            show(&envt, "[(min 3 1/2 2.0) (max 3 1/2 2.0) (max 7)]"),
+            "[1/2 3 7]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(floor -7/2) (ceil -7/2) (round -7/2) (round 2.5) (floor 3)]"
+            ),
+            "[-4 -3 -4 3 3]"
+        );
+        assert_eq!(
+            show(&envt, "[(pow 2 10) (pow 2 -2) (pow 2/3 2) (pow 4 0.5)]"),
+            "[1024 1/4 4/9 2.0]"
+        );
+        assert_eq!(show(&envt, "(pow 10 20)"), "100000000000000000000");
+        assert!(rep(&envt, "(pow 10 1000000000)").is_err());
+        assert!(rep(&envt, "(pow 1/10 -1000000000)").is_err());
+        assert_eq!(show(&envt, "(pow -1 1000000001)"), "-1");
+        assert_eq!(
+            show(
+                &envt,
+                "[(exp 0) (log 1) (sin 0) (cos 0) (tan 0) (atan2 0 1)]"
+            ),
+            "[1.0 0.0 0.0 1.0 0.0 0.0]"
+        );
+
+        assert_eq!(
+            show(&envt, "[(quot 7 2) (quot -7 2) (quot 7 -2) (quot -7.5 2)]"),
+            "[3 -3 -3 -3.0]"
+        );
+        assert_eq!(
+            show(&envt, "[(rem 7 2) (rem -7 2) (rem 7 -2) (rem -7/2 2)]"),
+            "[1 -1 1 -3/2]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(mod 7 2) (mod -7 2) (mod 7 -2) (mod -7/2 2) (mod -7.5 2)]"
+            ),
+            "[1 1 -1 1/2 0.5]"
+        );
+        assert!(rep(&envt, "(mod 1 0)").is_err());
+
+        assert_eq!(
+            show(
+                &envt,
+                "[(bit-and 12 10) (bit-or 12 10) (bit-xor 12 10) (bit-and -1 255)]"
+            ),
+            "[8 14 6 255]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(bit-shift-left 1 70) (bit-shift-right -16 2) (bit-shift-right 1 3)]"
+            ),
+            "[1180591620717411303424 -4 0]"
+        );
+        assert!(rep(&envt, "(bit-and 1.5 1)").is_err());
+        assert!(rep(&envt, "(bit-shift-left 1 100000000000)").is_err());
+        assert!(rep(&envt, "(bit-shift-left 1 2000000000)").is_err());
+        assert_eq!(show(&envt, "(bit-shift-left 0 2000000000)"), "0");
+        assert_eq!(
+            show(
+                &envt,
+                "(try* (bit-shift-left 1 100000000000) (catch* e :caught))"
+            ),
+            ":caught"
+        );
+        assert_eq!(show(&envt, "(bit-shift-right 1 100000000000)"), "0");
+
+        rep(&envt, "(rand-seed! 42)").unwrap();
+        let first = show(&envt, "[(rand) (rand-int 100) (rand 10)]");
+        rep(&envt, "(rand-seed! 42)").unwrap();
+        assert_eq!(show(&envt, "[(rand) (rand-int 100) (rand 10)]"), first);
+        assert_eq!(
+            show(&envt, "(let* [n (rand-int 3)] (if (< n 3) (>= n 0) false))"),
+            "true"
+        );
+        assert_eq!(
+            show(&envt, "(let* [x (rand)] (if (< x 1) (>= x 0) false))"),
+            "true"
+        );
+        assert!(rep(&envt, "(rand-int 0)").is_err());
+    }
+}
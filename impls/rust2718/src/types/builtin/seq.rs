@@ -0,0 +1,530 @@
+/*!
+Built-in functions on lists, vectors and maps.
+*/
+use std::sync::Arc;
+
+use crate::{
+    env::Env,
+    error::{err, rerr},
+    types::{BuiltinDef, EnvFunc, List, Map, StaticFunc},
+    MalErr, Res, Val,
+};
+
+pub const BUILTINS: &[BuiltinDef<StaticFunc>] = &[
+    (
+        "list",
+        &make_list,
+        "(list & xs)",
+        "A list of xs.",
+    ),
+    (
+        "list?",
+        &list_p,
+        "(list? x)",
+        "Whether x is a list.",
+    ),
+    (
+        "vector",
+        &vector,
+        "(vector & xs)",
+        "A vector of xs.",
+    ),
+    (
+        "vector?",
+        &vector_p,
+        "(vector? x)",
+        "Whether x is a vector.",
+    ),
+    (
+        "vec",
+        &vec,
+        "(vec coll)",
+        "A vector of the items of coll.",
+    ),
+    (
+        "sequential?",
+        &sequential_p,
+        "(sequential? x)",
+        "Whether x is a list or a vector.",
+    ),
+    (
+        "count",
+        &count,
+        "(count coll)",
+        "The number of items in coll, or characters in a string.",
+    ),
+    (
+        "empty?",
+        &is_empty,
+        "(empty? coll)",
+        "Whether coll has no items.",
+    ),
+    (
+        "cons",
+        &cons,
+        "(cons x coll)",
+        "A list of x followed by the items of coll.",
+    ),
+    (
+        "concat",
+        &concat,
+        "(concat & colls)",
+        "A list of the items of each of colls, in order.",
+    ),
+    (
+        "conj",
+        &conj,
+        "(conj coll & xs)",
+        "coll with xs added: at the front of a list, at the end of a vector, or\nas [key value] entries of a map.",
+    ),
+    (
+        "seq",
+        &seq,
+        "(seq coll)",
+        "A list of the items of coll, or nil if it has none.",
+    ),
+    (
+        "nth",
+        &nth,
+        "(nth coll n)",
+        "The item at index n of a list or vector; an error if there isn't one.",
+    ),
+    (
+        "first",
+        &first,
+        "(first coll)",
+        "The first item of coll, or nil.",
+    ),
+    (
+        "rest",
+        &rest,
+        "(rest coll)",
+        "A list of all but the first item of coll.",
+    ),
+    (
+        "hash-map",
+        &hash_map,
+        "(hash-map & kvs)",
+        "A map of the alternating keys and values kvs.",
+    ),
+    (
+        "map?",
+        &map_p,
+        "(map? x)",
+        "Whether x is a map.",
+    ),
+    (
+        "assoc",
+        &assoc,
+        "(assoc m & kvs)",
+        "A copy of the map m with the alternating keys and values kvs added.",
+    ),
+    (
+        "dissoc",
+        &dissoc,
+        "(dissoc m & ks)",
+        "A copy of the map m without the keys ks.",
+    ),
+    (
+        "get",
+        &get,
+        "(get coll key)\n(get coll key default)",
+        "The value at key in a map, or index in a vector; default (or nil) if\nthere isn't one.",
+    ),
+    (
+        "contains?",
+        &contains_p,
+        "(contains? coll key)",
+        "Whether a map has key, or a vector has the index key.",
+    ),
+    (
+        "keys",
+        &keys,
+        "(keys m)",
+        "A list of the keys of the map m.",
+    ),
+    (
+        "vals",
+        &vals,
+        "(vals m)",
+        "A list of the values of the map m.",
+    ),
+];
+
+/// Builtins that call functions passed to them.
+pub const ENV_BUILTINS: &[BuiltinDef<EnvFunc>] = &[
+    (
+        "apply",
+        &apply,
+        "(apply f & args coll)",
+        "Calls f with args followed by the items of coll.",
+    ),
+    (
+        "map",
+        &map,
+        "(map f coll)",
+        "A list of the results of calling f on each item of coll.",
+    ),
+];
+
+/**
+The items of any collection, in order: the elements of a list or vector,
+the `[key value]` entries of a map, or the characters of a string (as
+one-character strings). `nil` has no items.
+*/
+fn items(v: &Val) -> Result<Vec<Val>, MalErr> {
+    match v {
+        Val::Map(m, _) => Ok(m.iter().map(|(k, v)| Val::vec(vec![k, v])).collect()),
+        Val::String(s) => Ok(s.chars().map(|c| Val::from(c.to_string())).collect()),
+        Val::List(..) | Val::Vector(..) | Val::Nil => v.unwrap_seq(),
+        x => rerr(format!("{} is not a collection", x)),
+    }
+}
+
+fn unwrap_map(v: Val) -> Result<Arc<Map>, MalErr> {
+    match v {
+        Val::Map(m, _) => Ok(m),
+        Val::Nil => Ok(Arc::new(Map::default())),
+        x => rerr(format!("expected a map, not {}", &x)),
+    }
+}
+
+/// Turns a collection of `[key value]` entries into `(key, value)` pairs.
+fn entry(v: Val) -> Result<(Val, Val), MalErr> {
+    match v.unwrap_seq().as_deref() {
+        Ok([k, v]) => Ok((k.clone(), v.clone())),
+        _ => rerr(format!("expected a [key value] entry, not {}", &v)),
+    }
+}
+
+pub fn make_list(args: Arc<List>) -> Res {
+    Ok(args.into())
+}
+
+pub fn list_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::List(..)).into())
+}
+
+pub fn vector(args: Arc<List>) -> Res {
+    Ok(Val::from(args).unwrap_seq()?.into())
+}
+
+pub fn vector_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Vector(..)).into())
+}
+
+pub fn vec(args: Arc<List>) -> Res {
+    Ok(items(&args.car()?)?.into())
+}
+
+pub fn sequential_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::List(..) | Val::Vector(..)).into())
+}
+
+pub fn count(args: Arc<List>) -> Res {
+    let n = match args.car()? {
+        Val::List(list, _) => list.len(),
+        Val::Vector(v, _) => v.len() as i64,
+        Val::Map(m, _) => m.len() as i64,
+        Val::String(s) => s.chars().count() as i64,
+        Val::Nil => 0,
+        _ => return rerr("count requires a countable argument"),
+    };
+    Ok(n.into())
+}
+
+pub fn is_empty(args: Arc<List>) -> Res {
+    let empty = match args.car()? {
+        Val::List(list, _) => list.is_empty(),
+        Val::Vector(v, _) => v.is_empty(),
+        Val::Map(m, _) => m.is_empty(),
+        Val::String(s) => s.is_empty(),
+        Val::Nil => true,
+        _ => return rerr("empty? requires a countable argument"),
+    };
+    Ok(empty.into())
+}
+
+pub fn cons(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let val = args.pop()?;
+    match args.pop()? {
+        Val::List(list, _) => Ok(list.cons(val).into()),
+        coll => Ok(List::from_vec(items(&coll)?).cons(val).into()),
+    }
+}
+
+pub fn concat(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let mut v: Vec<Val> = Vec::new();
+    while let Some(coll) = args.next() {
+        v.extend(items(&coll)?);
+    }
+    Ok(List::from_vec(v).into())
+}
+
+/// Adds items where they're cheapest to add: to the front of a list, to
+/// the end of a vector, or as entries of a map.
+pub fn conj(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    match args.pop()? {
+        Val::List(mut list, _) => {
+            while let Some(val) = args.next() {
+                list = list.cons(val);
+            }
+            Ok(list.into())
+        }
+        Val::Nil => Ok(args.into()),
+        Val::Vector(v, _) => {
+            let mut v = v.as_ref().clone();
+            v.extend(Val::from(args).unwrap_seq()?);
+            Ok(v.into())
+        }
+        Val::Map(m, _) => {
+            let mut m = m.as_ref().clone();
+            while let Some(val) = args.next() {
+                let pairs = match val {
+                    Val::Map(other, _) => other.iter().collect(),
+                    val => vec![entry(val)?],
+                };
+                for (k, v) in pairs.into_iter() {
+                    m = m.assoc(k, v);
+                }
+            }
+            Ok(m.into())
+        }
+        x => rerr(format!("cannot conj onto {}", &x)),
+    }
+}
+
+/// A non-empty collection's items as a list, or else `nil`.
+pub fn seq(args: Arc<List>) -> Res {
+    let v = items(&args.car()?)?;
+    if v.is_empty() {
+        Ok(Val::Nil)
+    } else {
+        Ok(List::from_vec(v).into())
+    }
+}
+
+pub fn nth(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let coll = args.pop()?;
+    let n = match args.pop()? {
+        Val::Int(n) => n,
+        x => return rerr(format!("nth requires an integer index, not {}", &x)),
+    };
+
+    let i = usize::try_from(n).ok();
+    let val = match &coll {
+        Val::List(list, _) => i.and_then(|i| {
+            let mut list = list.clone();
+            for _ in 0..i {
+                list.next()?;
+            }
+            list.next()
+        }),
+        Val::Vector(v, _) => i.and_then(|i| v.get(i).cloned()),
+        x => return rerr(format!("nth requires a list or vector, not {}", x)),
+    };
+    val.ok_or_else(|| {
+        let len = coll.unwrap_seq().map_or(0, |v| v.len());
+        err(format!("index {} out of range for {} items", n, len))
+    })
+}
+
+pub fn first(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::List(list, _) => Ok(list.car().unwrap_or(Val::Nil)),
+        Val::Vector(v, _) => Ok(v.front().cloned().unwrap_or(Val::Nil)),
+        coll => Ok(items(&coll)?.into_iter().next().unwrap_or(Val::Nil)),
+    }
+}
+
+pub fn rest(args: Arc<List>) -> Res {
+    match args.car()? {
+        Val::List(list, _) => Ok(list.cdr().unwrap_or_else(|_| List::empty()).into()),
+        coll => Ok(List::from_vec(items(&coll)?.into_iter().skip(1).collect()).into()),
+    }
+}
+
+pub fn hash_map(args: Arc<List>) -> Res {
+    let mut m = Map::default();
+    let mut args = args.clone();
+    while let Some(k) = args.next() {
+        match args.next() {
+            Some(v) => m = m.assoc(k, v),
+            None => return rerr("hash-map requires an even number of arguments"),
+        };
+    }
+    Ok(m.into())
+}
+
+pub fn map_p(args: Arc<List>) -> Res {
+    Ok(matches!(args.car()?, Val::Map(..)).into())
+}
+
+pub fn assoc(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let mut m = unwrap_map(args.pop()?)?.as_ref().clone();
+    while let Some(k) = args.next() {
+        match args.next() {
+            Some(v) => m = m.assoc(k, v),
+            None => return rerr("assoc requires a value for every key"),
+        };
+    }
+    Ok(m.into())
+}
+
+pub fn dissoc(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let mut m = unwrap_map(args.pop()?)?.as_ref().clone();
+    while let Some(k) = args.next() {
+        m = m.dissoc(k);
+    }
+    Ok(m.into())
+}
+
+/// `(get coll key default)` looks up a key in a map or an index in a
+/// vector; `default` (or `nil`) if it isn't there.
+pub fn get(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let coll = args.pop()?;
+    let key = args.pop()?;
+    let default = args.next().unwrap_or(Val::Nil);
+
+    let found = match (coll, key) {
+        (Val::Map(m, _), k) => m.get(k),
+        (Val::Vector(v, _), Val::Int(n)) => usize::try_from(n).ok().and_then(|i| v.get(i).cloned()),
+        _ => None,
+    };
+    Ok(found.unwrap_or(default))
+}
+
+pub fn contains_p(args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let coll = args.pop()?;
+    let key = args.pop()?;
+
+    let found = match (coll, key) {
+        (Val::Map(m, _), k) => m.contains_key(k),
+        (Val::Vector(v, _), Val::Int(n)) => n >= 0 && (n as usize) < v.len(),
+        (Val::Nil, _) => false,
+        (x, _) => return rerr(format!("contains? requires a map or vector, not {}", &x)),
+    };
+    Ok(found.into())
+}
+
+pub fn keys(args: Arc<List>) -> Res {
+    let m = unwrap_map(args.car()?)?;
+    Ok(List::from_vec(m.iter().map(|(k, _)| k).collect()).into())
+}
+
+pub fn vals(args: Arc<List>) -> Res {
+    let m = unwrap_map(args.car()?)?;
+    Ok(List::from_vec(m.iter().map(|(_, v)| v).collect()).into())
+}
+
+/// `(apply f a b [c d])` calls `(f a b c d)`.
+pub fn apply(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = Val::from(args).unwrap_seq()?;
+    if args.len() < 2 {
+        return rerr("apply requires a function and a collection of arguments");
+    }
+    let func = args.remove(0).unwrap_func()?;
+    let last = args.pop().unwrap();
+    args.extend(items(&last)?);
+    func.call(envt, List::from_vec(args))
+}
+
+/// `(map f coll)` is a list of `f` applied to each item of `coll`.
+pub fn map(envt: &Arc<Env>, args: Arc<List>) -> Res {
+    let mut args = args.clone();
+    let func = args.pop()?.unwrap_func()?;
+    let coll = args.pop()?;
+
+    let v = items(&coll)?
+        .into_iter()
+        .map(|val| func.call(envt, List::from_val(val)))
+        .collect::<Result<Vec<_>, MalErr>>()?;
+    Ok(List::from_vec(v).into())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
+
+    #[test]
+    fn sequences() {
+        let envt = Env::default();
+        assert_eq!(show(&envt, "(count [1 2 3])"), "3");
+        assert_eq!(show(&envt, "(count {:a 1})"), "1");
+        assert_eq!(show(&envt, "(first [1 2])"), "1");
+        assert_eq!(show(&envt, "(rest [1 2])"), "(2)");
+        assert_eq!(show(&envt, "(nth (list 1 2) 1)"), "2");
+        assert_eq!(show(&envt, "(conj [1] 2 3)"), "[1 2 3]");
+        assert_eq!(show(&envt, "(conj (list 1) 2 3)"), "(3 2 1)");
+        assert_eq!(show(&envt, "(seq [])"), "nil");
+        assert_eq!(show(&envt, "(apply + 1 [2 3])"), "6");
+        assert_eq!(show(&envt, "(map (fn* (x) (* x x)) [1 2 3])"), "(1 4 9)");
+        assert_eq!(show(&envt, "(get (assoc {} :a 1) :a)"), "1");
+        assert_eq!(show(&envt, "(contains? (dissoc {:a 1} :a) :a)"), "false");
+        assert_eq!(show(&envt, "(keyword \"k\")"), ":k");
+        assert_eq!(show(&envt, "(fn? cond)"), "false");
+        assert_eq!(show(&envt, "(macro? cond)"), "true");
+        assert!(rep(&envt, "(nth [1] 5)").is_err());
+        assert!(rep(&envt, "(nth '(1) -1)").is_err());
+        assert_eq!(show(&envt, "[(nth '(1 2 3) 2) (nth [1 2 3] 0)]"), "[3 1]");
+        assert_eq!(
+            show(&envt, "[(first '()) (first []) (first nil)]"),
+            "[nil nil nil]"
+        );
+        assert_eq!(
+            show(
+                &envt,
+                "[(empty? '()) (empty? [1]) (empty? {}) (empty? \"\") (empty? nil)]"
+            ),
+            "[true false true true true]"
+        );
+    }
+
+    #[test]
+    fn long_lists() {
+        let envt = Env::default();
+        rep(
+            &envt,
+            "(def! upto (fn* [n acc] (if (= n 0) acc (upto (- n 1) (cons n acc)))))",
+        )
+        .unwrap();
+        rep(&envt, "(def! xs (upto 10000 ()))").unwrap();
+        assert_eq!(
+            show(&envt, "[(count xs) (first xs) (nth xs 9999) (empty? xs)]"),
+            "[10000 1 10000 false]"
+        );
+        assert_eq!(show(&envt, "(cons 0 [1 2])"), "(0 1 2)");
+    }
+
+    #[test]
+    fn persistent_collections() {
+        let envt = Env::default();
+        rep(&envt, "(def! m {:a 1})").unwrap();
+        rep(&envt, "(def! n (assoc m :b 2))").unwrap();
+        assert_eq!(show(&envt, "m"), "{:a 1}");
+        assert_eq!(show(&envt, "(dissoc n :a)"), "{:b 2}");
+        assert_eq!(show(&envt, "n"), "{:a 1 :b 2}");
+        assert_eq!(show(&envt, "(conj m [:c 3])"), "{:a 1 :c 3}");
+        assert_eq!(show(&envt, "m"), "{:a 1}");
+
+        rep(&envt, "(def! v [1 2])").unwrap();
+        rep(&envt, "(def! a (atom v))").unwrap();
+        rep(&envt, "(swap! a conj 3)").unwrap();
+        assert_eq!(show(&envt, "v"), "[1 2]");
+        assert_eq!(show(&envt, "@a"), "[1 2 3]");
+        assert_eq!(show(&envt, "(conj v 4)"), "[1 2 4]");
+        assert_eq!(show(&envt, "(with-meta v {:x 1})"), "[1 2]");
+        assert_eq!(show(&envt, "(meta v)"), "nil");
+    }
+}
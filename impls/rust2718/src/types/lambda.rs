@@ -7,24 +7,81 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-use crate::{env::Env, eval::eval, types::List, Res, Val};
+use crate::{
+    env::Env,
+    error::{err, rerr},
+    eval::eval,
+    types::{List, Pattern},
+    MalErr, Res, Val,
+};
+
+pub type StaticFunc = dyn Fn(Arc<List>) -> Res + Send + Sync;
+/// A builtin that needs the environment it was called from.
+pub type EnvFunc = dyn Fn(&Arc<Env>, Arc<List>) -> Res + Send + Sync;
 
-pub type StaticFunc = dyn Fn(Arc<List>) -> Res;
+/**
+A row of a builtin table: the builtin's name, its function, how it's
+called (one call form per line, as in `(get m k)`), and what it does.
+*/
+pub type BuiltinDef<F> = (&'static str, &'static F, &'static str, &'static str);
 
-pub trait Lambda: Display + Debug {
+pub trait Lambda: Display + Debug + Send + Sync {
     fn call(&self, envt: &Arc<Env>, args: Arc<List>) -> Res;
+
+    fn name(&self) -> Option<Arc<str>>;
+
+    /// Interpreted functions return themselves here so that `eval` can
+    /// evaluate their bodies in tail position instead of through `call`.
+    fn as_function(&self) -> Option<&Function> {
+        None
+    }
+
+    /// The docstring, if there is one.
+    fn doc(&self) -> Option<Arc<str>>;
+
+    /// How the function is called, as in `(f x)` and `(f x & more)`.
+    fn signatures(&self) -> Vec<String>;
+
+    /// A copy of this function with the docstring `doc`.
+    fn with_doc(&self, doc: Arc<str>) -> Arc<dyn Lambda>;
+
+    /// The signatures, then the docstring indented below them.
+    fn describe(&self) -> String {
+        let mut lines = self.signatures();
+        match self.doc() {
+            Some(doc) => lines.extend(doc.lines().map(|line| format!("  {}", line))),
+            None => lines.push("  (no documentation)".into()),
+        }
+        lines.join("\n")
+    }
 }
 
+#[derive(Clone)]
 pub struct Builtin {
     name: &'static str,
-    func: Arc<StaticFunc>,
+    func: Arc<EnvFunc>,
+    sig: &'static str,
+    doc: Option<Arc<str>>,
 }
 
 impl Builtin {
-    pub fn new(name: &'static str, func: &'static StaticFunc) -> Builtin {
+    pub fn new(def: &BuiltinDef<StaticFunc>) -> Builtin {
+        let &(name, func, sig, doc) = def;
+        Builtin {
+            name,
+            func: Arc::new(move |_: &Arc<Env>, args| func(args)),
+            sig,
+            doc: Some(doc.into()),
+        }
+    }
+
+    pub fn with_env(def: &BuiltinDef<EnvFunc>) -> Builtin {
+        let &(name, func, sig, doc) = def;
         Builtin {
             name,
             func: Arc::new(func),
+            sig,
+            doc: Some(doc.into()),
         }
     }
 }
@@ -48,8 +105,27 @@ impl PartialOrd for Builtin {
 }
 
 impl Lambda for Builtin {
-    fn call(&self, _: &Arc<Env>, args: Arc<List>) -> Res {
-        (self.func)(args)
+    fn call(&self, envt: &Arc<Env>, args: Arc<List>) -> Res {
+        (self.func)(envt, args)
+    }
+
+    fn name(&self) -> Option<Arc<str>> {
+        Some(self.name.into())
+    }
+
+    fn doc(&self) -> Option<Arc<str>> {
+        self.doc.clone()
+    }
+
+    fn signatures(&self) -> Vec<String> {
+        self.sig.lines().map(String::from).collect()
+    }
+
+    fn with_doc(&self, doc: Arc<str>) -> Arc<dyn Lambda> {
+        Arc::new(Builtin {
+            doc: Some(doc),
+            ..self.clone()
+        })
     }
 }
 
@@ -65,47 +141,217 @@ impl Debug for Builtin {
     }
 }
 
+/// The parameter list of a `Function`: some number of required
+/// parameters, optionally followed by `& rest`.
+#[derive(Clone, Debug, Default)]
+pub struct Params {
+    pub required: Vec<Pattern>,
+    pub rest: Option<Pattern>,
+}
+
+impl Params {
+    /// Whether a call with `n` arguments satisfies these parameters.
+    pub fn accepts(&self, n: usize) -> bool {
+        match self.rest {
+            None => n == self.required.len(),
+            Some(_) => n >= self.required.len(),
+        }
+    }
+}
+
+impl Display for Params {
+    /// Describes the expected arity, as in "2" or "at least 1".
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.rest {
+            None => write!(f, "{}", self.required.len()),
+            Some(_) => write!(f, "at least {}", self.required.len()),
+        }
+    }
+}
+
+/// One arity of a `Function`: a parameter list and the body form to
+/// evaluate when a call matches it.
+#[derive(Clone, Debug)]
+pub struct Clause {
+    pub params: Params,
+    pub form: Val,
+}
+
 pub struct Function {
     name: RwLock<Option<Arc<str>>>,
-    args: Vec<Arc<str>>,
+    doc: Option<Arc<str>>,
+    clauses: Vec<Clause>,
     envt: Arc<Env>,
-    form: Val,
+    is_macro: bool,
 }
 
 impl Function {
-    pub fn define(args: Vec<Arc<str>>, envt: &Arc<Env>, form: Val) -> Function {
-        Function {
+    /**
+    Creates a function from one or more clauses.
+
+    No two clauses may take the same fixed number of arguments, and at
+    most one may take `& rest` arguments.
+    */
+    pub fn define(clauses: Vec<Clause>, envt: &Arc<Env>) -> Result<Function, MalErr> {
+        if clauses.is_empty() {
+            return rerr("function requires at least one clause");
+        }
+        for (n, clause) in clauses.iter().enumerate() {
+            for other in clauses[..n].iter() {
+                match (&clause.params.rest, &other.params.rest) {
+                    (Some(_), Some(_)) => {
+                        return rerr("function can have only one variadic clause");
+                    }
+                    (None, None) if clause.params.required.len() == other.params.required.len() => {
+                        return rerr(format!(
+                            "function has more than one clause with arity {}",
+                            &clause.params
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Function {
             name: RwLock::new(None),
-            args,
+            doc: None,
+            clauses,
             envt: envt.clone(),
-            form,
+            is_macro: false,
+        })
+    }
+
+    /// A copy of this function that `eval` will treat as a macro.
+    pub fn to_macro(&self) -> Function {
+        Function {
+            is_macro: true,
+            ..self.copy()
+        }
+    }
+
+    /// This function, with the docstring `doc`.
+    pub fn documented(self, doc: Arc<str>) -> Function {
+        Function {
+            doc: Some(doc),
+            ..self
         }
     }
 
+    fn copy(&self) -> Function {
+        Function {
+            name: RwLock::new(self.name.read().unwrap().clone()),
+            doc: self.doc.clone(),
+            clauses: self.clauses.clone(),
+            envt: self.envt.clone(),
+            is_macro: self.is_macro,
+        }
+    }
+
+    pub fn is_macro(&self) -> bool {
+        self.is_macro
+    }
+
     pub fn set_name(&self, name: &Arc<str>) {
         *self.name.write().unwrap() = Some(name.clone());
     }
-}
 
-impl Lambda for Function {
-    fn call(&self, envt: &Arc<Env>, args: Arc<List>) -> Res {
-        let mut bindings: Vec<(Arc<str>, Val)> = Vec::with_capacity(self.args.len());
+    /// The clause a call with `n` arguments should use. A clause with a
+    /// matching fixed arity wins over the variadic one.
+    fn clause_for(&self, n: usize) -> Option<&Clause> {
+        self.clauses
+            .iter()
+            .find(|c| c.params.rest.is_none() && c.params.accepts(n))
+            .or_else(|| self.clauses.iter().find(|c| c.params.accepts(n)))
+    }
+
+    fn arity_err(&self, n_args: usize) -> MalErr {
+        let name = match self.name() {
+            Some(name) => name,
+            None => "anonymous function".into(),
+        };
+        let arities: Vec<String> = self.clauses.iter().map(|c| c.params.to_string()).collect();
+        let expected = match arities.split_last() {
+            Some((last, init)) if !init.is_empty() => format!("{} or {}", init.join(", "), last),
+            _ => arities.concat(),
+        };
+        err(format!(
+            "wrong number of arguments to {}: expected {}, got {}",
+            &name, &expected, n_args
+        ))
+    }
+
+    /// Binds the (already evaluated) `args` in a new child of the closure's
+    /// environment; returns that environment and the body form to evaluate
+    /// in it.
+    pub fn bind(&self, args: Arc<List>) -> Result<(Arc<Env>, Val), MalErr> {
+        let n_args = args.len() as usize;
+        let clause = self
+            .clause_for(n_args)
+            .ok_or_else(|| self.arity_err(n_args))?;
+        let params = &clause.params;
+
+        let fn_envt = Env::child_of(&self.envt);
         let mut args = args.clone();
-        for sym in self.args.iter() {
-            let val = eval(envt, args.pop()?)?;
-            bindings.push((sym.clone(), val));
+        for pat in params.required.iter() {
+            pat.bind(&fn_envt, args.pop()?)?;
+        }
+        if let Some(pat) = &params.rest {
+            pat.bind(&fn_envt, args.into())?;
         }
 
-        let fn_env = Env::binding(&self.envt, bindings);
-        eval(&fn_env, self.form.clone())
+        Ok((fn_envt, clause.form.clone()))
+    }
+}
+
+impl Lambda for Function {
+    fn call(&self, _: &Arc<Env>, args: Arc<List>) -> Res {
+        let (fn_env, form) = self.bind(args)?;
+        eval(&fn_env, form)
+    }
+
+    fn name(&self) -> Option<Arc<str>> {
+        self.name.read().unwrap().clone()
+    }
+
+    fn as_function(&self) -> Option<&Function> {
+        Some(self)
+    }
+
+    fn doc(&self) -> Option<Arc<str>> {
+        self.doc.clone()
+    }
+
+    fn signatures(&self) -> Vec<String> {
+        let name = self.name().unwrap_or_else(|| "fn*".into());
+        self.clauses
+            .iter()
+            .map(|c| {
+                let mut words: Vec<String> = vec![name.to_string()];
+                words.extend(c.params.required.iter().map(|p| p.to_string()));
+                if let Some(rest) = &c.params.rest {
+                    words.push(format!("& {}", rest));
+                }
+                format!("({})", words.join(" "))
+            })
+            .collect()
+    }
+
+    fn with_doc(&self, doc: Arc<str>) -> Arc<dyn Lambda> {
+        Arc::new(self.copy().documented(doc))
     }
 }
 
 impl Display for Function {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_macro {
+            "macro"
+        } else {
+            "interpreted function"
+        };
         match *self.name.read().unwrap() {
-            None => write!(f, "<(anonymous interpreted function)>"),
-            Some(ref a) => write!(f, "<{} (interpreted function)>", a),
+            None => write!(f, "<(anonymous {})>", kind),
+            Some(ref a) => write!(f, "<{} ({})>", a, kind),
         }
     }
 }
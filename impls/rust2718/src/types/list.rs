@@ -3,17 +3,39 @@ The classic singly-linked list.
 */
 use std::{ops::Deref, sync::Arc};
 
+use once_cell::sync::Lazy;
+
 use crate::{error::rerr, types::Val, MalErr, Res};
 
-#[derive(Debug, PartialEq)]
+/// The one empty list, shared by every list's end.
+static EMPTY: Lazy<Arc<List>> = Lazy::new(|| Arc::new(List::Nil));
+
+#[derive(Debug)]
 pub enum List {
     Node { val: Val, next: Arc<List> },
     Nil,
 }
 
+impl Drop for List {
+    /// Unlinks the tail one node at a time while this list is its only
+    /// owner, instead of recursing once per node.
+    fn drop(&mut self) {
+        let mut next = match self {
+            List::Node { next, .. } => std::mem::replace(next, List::empty()),
+            List::Nil => return,
+        };
+        while let Ok(mut node) = Arc::try_unwrap(next) {
+            next = match &mut node {
+                List::Node { next, .. } => std::mem::replace(next, List::empty()),
+                List::Nil => return,
+            };
+        }
+    }
+}
+
 impl List {
     pub fn empty() -> Arc<List> {
-        Arc::new(List::Nil)
+        EMPTY.clone()
     }
 
     pub fn is_empty(self: &Arc<List>) -> bool {
@@ -98,11 +120,23 @@ impl List {
     pub fn from_val(v: Val) -> Arc<List> {
         List::empty().cons(v)
     }
+
+    pub fn from_vec(mut v: Vec<Val>) -> Arc<List> {
+        let mut list = List::empty();
+        while let Some(val) = v.pop() {
+            list = list.cons(val);
+        }
+        list
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        env::Env,
+        eval::test::{rep, show},
+    };
 
     #[test]
     fn list_nexting() {
@@ -116,4 +150,24 @@ mod test {
         }
         println!("{}", Val::from(b));
     }
+
+    #[test]
+    fn long_lists_drop() {
+        let mut list = List::empty();
+        for i in 0..1_000_000 {
+            list = list.cons(i);
+        }
+        let shared = list.cdr().unwrap();
+        drop(list);
+        assert_eq!(shared.len(), 999_999);
+        drop(shared);
+
+        let envt = Env::default();
+        rep(
+            &envt,
+            "(def! upto (fn* [n acc] (if (= n 0) acc (upto (- n 1) (conj acc n)))))",
+        )
+        .unwrap();
+        assert_eq!(show(&envt, "(count (upto 100000 ()))"), "100000");
+    }
 }
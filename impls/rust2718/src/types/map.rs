@@ -1,119 +1,86 @@
 /*!
 The MAP type.
-*/
-
-use std::{
-    collections::BTreeMap,
-    sync::{Arc, RwLock},
-};
-
-use ordered_float::OrderedFloat;
 
-use crate::{error::rerr, MalErr, Res, Val};
+A `Map` is persistent: `assoc` and `dissoc` return new versions that
+share all but O(log n) of their structure with the old one, which is
+left as it was. Values that share a map never see each other's changes.
 
-#[allow(dead_code)]
-#[derive(Clone, Debug, PartialOrd, PartialEq, Ord, Eq)]
-enum Key {
-    Int(i64),
-    Float(OrderedFloat<f64>),
-    Keyword(Arc<str>),
-    String(Arc<str>),
-    Symbol(Arc<str>),
-}
-
-impl From<&Key> for Val {
-    fn from(k: &Key) -> Val {
-        match k {
-            Key::Int(i) => Val::Int(*i),
-            Key::Float(x) => Val::Float(*x),
-            Key::Keyword(a) => Val::Keyword(a.clone()),
-            Key::String(a) => Val::String(a.clone()),
-            Key::Symbol(a) => Val::Symbol(a.clone()),
-        }
-    }
-}
+Any value can be a key. Keys are found by `=`, so `1` and `1.0` are the
+same key, as are a list and a vector with the same items.
+*/
 
-impl TryFrom<Val> for Key {
-    type Error = MalErr;
+use im::OrdMap;
 
-    fn try_from(v: Val) -> Result<Self, Self::Error> {
-        let key = match v {
-            Val::Int(n) => Key::Int(n),
-            Val::Float(x) => Key::Float(x),
-            Val::String(a) => Key::String(a.clone()),
-            Val::Symbol(a) => Key::Symbol(a.clone()),
-            Val::Keyword(a) => Key::Keyword(a.clone()),
-            _ => return rerr("invalid Map key"),
-        };
-        Ok(key)
-    }
-}
+use crate::Val;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Map {
-    map: RwLock<BTreeMap<Key, Val>>,
+    map: OrdMap<Val, Val>,
 }
 
-impl Default for Map {
-    fn default() -> Self {
-        let map = RwLock::new(BTreeMap::default());
-        Self { map }
+impl Map {
+    /// This map with `k` mapped to `v`.
+    pub fn assoc(mut self, k: Val, v: Val) -> Map {
+        self.map.insert(k, v);
+        self
     }
-}
 
-impl Map {
-    pub fn insert(self: &Arc<Map>, k: Val, v: Val) -> Res {
-        let key = Key::try_from(k)?;
+    /// This map without `k`.
+    pub fn dissoc(mut self, k: Val) -> Map {
+        self.map.remove(&k);
+        self
+    }
 
-        self.map.write().unwrap().insert(key, v.clone());
-        Ok(self.clone().into())
+    pub fn get(&self, k: Val) -> Option<Val> {
+        self.map.get(&k).cloned()
     }
 
-    pub fn get(self: &Arc<Map>, k: Val) -> Option<Val> {
-        let k = Key::try_from(k).ok()?;
+    pub fn contains_key(&self, k: Val) -> bool {
+        self.map.contains_key(&k)
+    }
 
-        self.map.read().unwrap().get(&k).cloned()
+    pub fn len(&self) -> usize {
+        self.map.len()
     }
 
-    pub fn iter(self: &Arc<Map>) -> MapIter {
-        let guard = self.map.read().unwrap();
-        let mut values: Vec<(Val, Val)> = Vec::with_capacity(guard.len());
-        for (k, v) in guard.iter() {
-            let key: Val = k.into();
-            let val: Val = v.clone();
-            values.push((key, val))
-        }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
 
-        MapIter { values }
+    /// The entries, in order of their keys.
+    pub fn iter(&self) -> impl Iterator<Item = (Val, Val)> + '_ {
+        self.map.iter().map(|(k, v)| (k.clone(), v.clone()))
     }
 }
 
 impl PartialEq for Map {
     fn eq(&self, other: &Self) -> bool {
-        let s = self.map.read().unwrap();
-        let t = other.map.read().unwrap();
-        if s.len() != t.len() {
-            return false;
-        }
-
-        for (k, v) in s.iter() {
-            if t.get(k) != Some(v) {
-                return false;
-            }
-        }
-
-        true
+        self.map == other.map
     }
 }
 
-pub struct MapIter {
-    values: Vec<(Val, Val)>,
-}
-
-impl Iterator for MapIter {
-    type Item = (Val, Val);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.values.pop()
+#[cfg(test)]
+mod test {
+    use crate::{env::Env, eval::test::show};
+
+    #[test]
+    fn values_as_keys() {
+        let envt = Env::default();
+        assert_eq!(show(&envt, "(get {[1 2] :v} '(1 2))"), ":v");
+        assert_eq!(show(&envt, "(get {1 :one} 1.0)"), ":one");
+        assert_eq!(show(&envt, "(count {1 :a 1.0 :b 2/2 :c})"), "1");
+        assert_eq!(show(&envt, "(get {{:a 1} :m nil :n} {:a 1})"), ":m");
+        assert_eq!(show(&envt, "(get {nil :n} nil)"), ":n");
+        assert_eq!(
+            show(
+                &envt,
+                "(let* [f (fn* [] 1) a (atom 0)] [(get {f :f a :a} f) (get {f :f} (fn* [] 1))])"
+            ),
+            "[:f nil]"
+        );
+        assert_eq!(
+            show(&envt, "(keys {:b 1 \"a\" 2 3 3 nil 4})"),
+            "(nil 3 \"a\" :b)"
+        );
     }
 }
@@ -0,0 +1,131 @@
+/*!
+Numbers taken apart for arithmetic, at the levels of the tower: integers
+(`Int`s, or `BigInt`s when they would overflow), then exact ratios, then
+floats.
+*/
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::{error::rerr, MalErr, Val};
+
+/// A number at one level of the tower; each level can represent every
+/// value of the levels before it.
+pub enum Num {
+    Int(i64),
+    Big(BigInt),
+    Ratio(BigRational),
+    Float(f64),
+}
+
+impl TryFrom<Val> for Num {
+    type Error = MalErr;
+
+    fn try_from(v: Val) -> Result<Num, MalErr> {
+        match v {
+            Val::Int(n) => Ok(Num::Int(n)),
+            Val::BigInt(n) => Ok(Num::Big(n.as_ref().clone())),
+            Val::Ratio(r) => Ok(Num::Ratio(r.as_ref().clone())),
+            Val::Float(x) => Ok(Num::Float(x.into())),
+            x => rerr(format!("{} is not a number", &x)),
+        }
+    }
+}
+
+impl From<Num> for Val {
+    fn from(n: Num) -> Val {
+        match n {
+            Num::Int(n) => n.into(),
+            Num::Big(n) => n.into(),
+            Num::Ratio(r) => r.into(),
+            Num::Float(x) => x.into(),
+        }
+    }
+}
+
+impl Num {
+    pub fn level(&self) -> u8 {
+        match self {
+            Num::Int(_) => 0,
+            Num::Big(_) => 1,
+            Num::Ratio(_) => 2,
+            Num::Float(_) => 3,
+        }
+    }
+
+    /// This number at `level`, which is at least its own.
+    pub fn into_level(self, level: u8) -> Num {
+        match (self, level) {
+            (Num::Int(n), 1) => Num::Big(n.into()),
+            (Num::Int(n), 2) => Num::Ratio(BigInt::from(n).into()),
+            (Num::Big(n), 2) => Num::Ratio(n.into()),
+            (n, 3) => Num::Float(n.to_f64()),
+            (n, _) => n,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Num::Int(n) => *n as f64,
+            Num::Big(n) => n.to_f64().unwrap_or(f64::NAN),
+            Num::Ratio(r) => r.to_f64().unwrap_or(f64::NAN),
+            Num::Float(x) => *x,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Num::Int(n) => *n == 0,
+            Num::Big(n) => n.is_zero(),
+            Num::Ratio(r) => r.is_zero(),
+            Num::Float(x) => *x == 0.0,
+        }
+    }
+}
+
+/// `a` and `b` as numbers at the level of the higher of them.
+pub fn promote(a: Val, b: Val) -> Result<(Num, Num), MalErr> {
+    let (a, b) = (Num::try_from(a)?, Num::try_from(b)?);
+    let level = a.level().max(b.level());
+    Ok((a.into_level(level), b.into_level(level)))
+}
+
+/**
+Compares `a` and `b` exactly, even an integer too big for a float with
+a float; `None` if either is NaN.
+*/
+pub fn compare(a: Val, b: Val) -> Result<Option<Ordering>, MalErr> {
+    let (a, b) = (Num::try_from(a)?, Num::try_from(b)?);
+    // A finite float is an exact ratio, so compare it as one with an
+    // exact number.
+    let exact = |n: Num| match n {
+        Num::Float(x) => BigRational::from_float(x).map_or(Num::Float(x), Num::Ratio),
+        n => n,
+    };
+    let (a, b) = match (&a, &b) {
+        (Num::Float(_), Num::Float(_)) => (a, b),
+        (Num::Float(_), _) | (_, Num::Float(_)) => (exact(a), exact(b)),
+        _ => (a, b),
+    };
+
+    // Any float left beside an exact number is infinite or NaN, and so
+    // beyond it whatever its size.
+    let beyond = |x: f64| x.partial_cmp(&0.0);
+    let ord = match (a, b) {
+        (Num::Float(x), Num::Float(y)) => x.partial_cmp(&y),
+        (Num::Float(x), _) => beyond(x),
+        (_, Num::Float(y)) => beyond(y).map(Ordering::reverse),
+        (a, b) => {
+            let level = a.level().max(b.level());
+            match (a.into_level(level), b.into_level(level)) {
+                (Num::Int(n), Num::Int(m)) => n.partial_cmp(&m),
+                (Num::Big(n), Num::Big(m)) => n.partial_cmp(&m),
+                (Num::Ratio(r), Num::Ratio(s)) => r.partial_cmp(&s),
+                _ => None,
+            }
+        }
+    };
+    Ok(ord)
+}
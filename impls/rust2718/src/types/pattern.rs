@@ -0,0 +1,212 @@
+/*!
+Destructuring patterns for `let*` bindings and `fn*` parameters.
+
+```text
+(let* [[a b & more] [1 2 3 4]
+       {:keys [x y] :or {y 0} :as m} {:x 1}]
+  ...)
+```
+*/
+use std::{
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+use crate::{env::Env, error::rerr, eval::eval, types::List, MalErr, Val};
+
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Binds the whole value to a name.
+    Symbol(Arc<str>),
+    /// Binds the elements of a list or vector positionally.
+    Seq {
+        form: Val,
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+        all: Option<Arc<str>>,
+    },
+    /// Binds the values of a map by key.
+    Map {
+        form: Val,
+        keys: Vec<(Pattern, Val)>,
+        defaults: Vec<(Arc<str>, Val)>,
+        all: Option<Arc<str>>,
+    },
+}
+
+/// The pieces of a sequential pattern: `items & rest :as all`.
+pub struct SeqParts {
+    pub items: Vec<Pattern>,
+    pub rest: Option<Pattern>,
+    pub all: Option<Arc<str>>,
+}
+
+impl SeqParts {
+    pub fn read(forms: Vec<Val>) -> Result<SeqParts, MalErr> {
+        let mut parts = SeqParts {
+            items: Vec::new(),
+            rest: None,
+            all: None,
+        };
+
+        let mut forms = forms.into_iter();
+        while let Some(form) = forms.next() {
+            match &form {
+                Val::Symbol(s) if s.as_ref() == "&" => match (forms.next(), &parts.rest) {
+                    (Some(rest), None) => parts.rest = Some(Pattern::read(&rest)?),
+                    _ => return rerr("& must be followed by exactly one pattern"),
+                },
+                Val::Keyword(s) if s.as_ref() == "as" => match (forms.next(), &parts.all) {
+                    (Some(all), None) => parts.all = Some(all.unwrap_symbol()?),
+                    _ => return rerr(":as must be followed by exactly one symbol"),
+                },
+                _ if parts.rest.is_some() || parts.all.is_some() => {
+                    return rerr(format!("unexpected {} after & or :as", &form));
+                }
+                _ => parts.items.push(Pattern::read(&form)?),
+            }
+        }
+
+        Ok(parts)
+    }
+}
+
+impl Pattern {
+    pub fn read(form: &Val) -> Result<Pattern, MalErr> {
+        match form {
+            Val::Symbol(s) => Ok(Pattern::Symbol(s.clone())),
+            Val::List(_, _) | Val::Vector(_, _) => {
+                let parts = SeqParts::read(form.unwrap_seq()?)?;
+                Ok(Pattern::Seq {
+                    form: form.clone(),
+                    items: parts.items,
+                    rest: parts.rest.map(Box::new),
+                    all: parts.all,
+                })
+            }
+            Val::Map(m, _) => {
+                let mut keys: Vec<(Pattern, Val)> = Vec::new();
+                let mut defaults: Vec<(Arc<str>, Val)> = Vec::new();
+                let mut all: Option<Arc<str>> = None;
+
+                for (k, v) in m.iter() {
+                    let directive = match &k {
+                        Val::Keyword(s) => s.as_ref(),
+                        _ => "",
+                    };
+                    match directive {
+                        "keys" | "strs" | "syms" => {
+                            for sym in v.unwrap_seq()? {
+                                let sym = sym.unwrap_symbol()?;
+                                let key = match directive {
+                                    "keys" => Val::Keyword(sym.clone()),
+                                    "strs" => Val::String(sym.clone()),
+                                    _ => Val::Symbol(sym.clone()),
+                                };
+                                keys.push((Pattern::Symbol(sym), key));
+                            }
+                        }
+                        "or" => match v {
+                            Val::Map(or, _) => {
+                                for (sym, default) in or.iter() {
+                                    defaults.push((sym.unwrap_symbol()?, default));
+                                }
+                            }
+                            _ => return rerr(format!(":or requires a map, not {}", &v)),
+                        },
+                        "as" => all = Some(v.unwrap_symbol()?),
+                        _ => keys.push((Pattern::read(&k)?, v)),
+                    }
+                }
+
+                Ok(Pattern::Map {
+                    form: form.clone(),
+                    keys,
+                    defaults,
+                    all,
+                })
+            }
+            x => rerr(format!("invalid binding pattern: {}", x)),
+        }
+    }
+
+    /// Binds the names in this pattern to the matching parts of `val` in
+    /// `envt`. `:or` defaults are evaluated in `envt`, too.
+    pub fn bind(&self, envt: &Arc<Env>, val: Val) -> Result<(), MalErr> {
+        match self {
+            Pattern::Symbol(s) => envt.set(s, val),
+            Pattern::Seq {
+                form,
+                items,
+                rest,
+                all,
+            } => {
+                let elts = match &val {
+                    Val::List(_, _) | Val::Vector(_, _) | Val::Nil => val.unwrap_seq()?,
+                    _ => {
+                        return rerr(format!(
+                            "cannot destructure {} with sequential pattern {}",
+                            &val, form
+                        ))
+                    }
+                };
+
+                let mut elts = elts.into_iter();
+                for pat in items.iter() {
+                    pat.bind(envt, elts.next().unwrap_or(Val::Nil))?;
+                }
+                if let Some(pat) = rest {
+                    pat.bind(envt, List::from_vec(elts.collect()).into())?;
+                }
+                if let Some(s) = all {
+                    envt.set(s, val);
+                }
+            }
+            Pattern::Map {
+                form,
+                keys,
+                defaults,
+                all,
+            } => {
+                let map = match &val {
+                    Val::Map(m, _) => Some(m.clone()),
+                    Val::Nil => None,
+                    _ => {
+                        return rerr(format!(
+                            "cannot destructure {} with map pattern {}",
+                            &val, form
+                        ))
+                    }
+                };
+
+                for (pat, key) in keys.iter() {
+                    let found = map.as_ref().and_then(|m| m.get(key.clone()));
+                    let v = match (found, pat) {
+                        (Some(v), _) => v,
+                        (None, Pattern::Symbol(s)) => match defaults.iter().find(|d| &d.0 == s) {
+                            Some((_, default)) => eval(envt, default.clone())
+                                .map_err(|e| e.wrap(format!("in :or default for {}", s)))?,
+                            None => Val::Nil,
+                        },
+                        (None, _) => Val::Nil,
+                    };
+                    pat.bind(envt, v)?;
+                }
+                if let Some(s) = all {
+                    envt.set(s, val);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Pattern {
+    /// The pattern as it was written.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Symbol(s) => write!(f, "{}", s),
+            Pattern::Seq { form, .. } | Pattern::Map { form, .. } => write!(f, "{}", form),
+        }
+    }
+}